@@ -1,11 +1,102 @@
 use clap::{Args, Parser, ValueEnum};
+use serde::Deserialize;
+use std::collections::HashMap;
+use utoipa::ToSchema;
 
-#[derive(Clone, Copy, Debug, ValueEnum)]
+use crate::image::ImageFetchErrorKind;
+
+#[derive(Clone, Copy, Debug, ValueEnum, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
 pub enum ImageFallbackBehavior {
     /// Skip image element if fetch fails
     Skip,
     /// Return error if fetch fails
     Error,
+    /// Render the configured placeholder image if fetch fails
+    Placeholder,
+}
+
+/// Image fetch error kinds that can be given their own fallback behavior
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum FallbackErrorKind {
+    Request,
+    Timeout,
+    HttpStatus,
+    TooLarge,
+    TooComplex,
+    InvalidContentType,
+    PrivateIpBlocked,
+    HostNotAllowed,
+    InvalidUrl,
+    CircuitOpen,
+}
+
+impl From<FallbackErrorKind> for ImageFetchErrorKind {
+    fn from(kind: FallbackErrorKind) -> Self {
+        match kind {
+            FallbackErrorKind::Request => Self::Request,
+            FallbackErrorKind::Timeout => Self::Timeout,
+            FallbackErrorKind::HttpStatus => Self::HttpStatus,
+            FallbackErrorKind::TooLarge => Self::TooLarge,
+            FallbackErrorKind::TooComplex => Self::TooComplex,
+            FallbackErrorKind::InvalidContentType => Self::InvalidContentType,
+            FallbackErrorKind::PrivateIpBlocked => Self::PrivateIpBlocked,
+            FallbackErrorKind::HostNotAllowed => Self::HostNotAllowed,
+            FallbackErrorKind::InvalidUrl => Self::InvalidUrl,
+            FallbackErrorKind::CircuitOpen => Self::CircuitOpen,
+        }
+    }
+}
+
+/// Fallback behavior for failed image fetches, by error kind
+///
+/// Per-kind overrides set by the operator always apply; a request's `on_image_error` only
+/// replaces the global fallback for kinds without one.
+#[derive(Clone, Debug)]
+pub struct FallbackPolicy {
+    default: ImageFallbackBehavior,
+    by_kind: HashMap<ImageFetchErrorKind, ImageFallbackBehavior>,
+    /// Image source rendered by the `placeholder` behavior
+    pub placeholder: Option<String>,
+}
+
+impl FallbackPolicy {
+    /// Build from the global fallback and `kind=behavior` overrides
+    pub fn from_settings(settings: &ImageSettings) -> Result<Self, String> {
+        let mut by_kind = HashMap::new();
+        for entry in &settings.fallback_by_error {
+            let invalid = || {
+                format!(
+                    "invalid fallback override (expected kind=behavior): {}",
+                    entry
+                )
+            };
+            let (kind, behavior) = entry.split_once('=').ok_or_else(invalid)?;
+            let kind = FallbackErrorKind::from_str(kind.trim(), true).map_err(|_| invalid())?;
+            let behavior =
+                ImageFallbackBehavior::from_str(behavior.trim(), true).map_err(|_| invalid())?;
+            by_kind.insert(kind.into(), behavior);
+        }
+
+        Ok(Self {
+            default: settings.fallback,
+            by_kind,
+            placeholder: settings.placeholder.clone(),
+        })
+    }
+
+    /// Behavior for a failure of `kind`, given the behavior the request asked for, if any
+    pub fn behavior(
+        &self,
+        kind: ImageFetchErrorKind,
+        requested: Option<ImageFallbackBehavior>,
+    ) -> ImageFallbackBehavior {
+        self.by_kind
+            .get(&kind)
+            .copied()
+            .or(requested)
+            .unwrap_or(self.default)
+    }
 }
 
 /// Default values for OG image fields
@@ -43,25 +134,145 @@ pub struct ImageSettings {
     #[arg(long, default_value = "5242880", env = "OGIS_LOGO_MAX_SIZE")]
     pub max_size_bytes: usize,
 
+    /// Maximum decoded pixel count (width x height) for raster images
+    #[arg(long, default_value = "40000000", env = "OGIS_LOGO_MAX_PIXELS")]
+    pub max_pixels: u64,
+
+    /// Maximum number of elements in an SVG image
+    #[arg(long, default_value = "10000", env = "OGIS_LOGO_SVG_MAX_NODES")]
+    pub svg_max_nodes: usize,
+
+    /// Maximum element nesting depth in an SVG image
+    #[arg(long, default_value = "64", env = "OGIS_LOGO_SVG_MAX_DEPTH")]
+    pub svg_max_depth: usize,
+
     /// Logo cache maximum entries
     #[arg(long, default_value = "100", env = "OGIS_LOGO_CACHE_SIZE")]
     pub cache_size: u64,
 
-    /// Logo cache TTL in seconds (default: 1 hour)
+    /// Logo cache TTL in seconds when the origin sends no cache headers (default: 1 hour)
     #[arg(long, default_value = "3600", env = "OGIS_LOGO_CACHE_TTL")]
     pub cache_ttl_secs: u64,
 
+    /// Minimum logo cache TTL in seconds, applied to origin cache headers (default: 1 minute)
+    #[arg(long, default_value = "60", env = "OGIS_LOGO_CACHE_MIN_TTL")]
+    pub cache_min_ttl_secs: u64,
+
+    /// Maximum logo cache TTL in seconds, applied to origin cache headers (default: 1 day)
+    #[arg(long, default_value = "86400", env = "OGIS_LOGO_CACHE_MAX_TTL")]
+    pub cache_max_ttl_secs: u64,
+
+    /// How long stale logos are served while being revalidated, in seconds (default: 1 day)
+    #[arg(long, default_value = "86400", env = "OGIS_LOGO_CACHE_STALE")]
+    pub cache_stale_secs: u64,
+
+    /// Negative cache TTL in seconds for connection and other request failures
+    #[arg(long, default_value = "30", env = "OGIS_LOGO_NEGATIVE_TTL_REQUEST")]
+    pub negative_ttl_request_secs: u64,
+
+    /// Negative cache TTL in seconds for timed out fetches
+    #[arg(long, default_value = "60", env = "OGIS_LOGO_NEGATIVE_TTL_TIMEOUT")]
+    pub negative_ttl_timeout_secs: u64,
+
+    /// Negative cache TTL in seconds for non-success HTTP statuses (e.g. 404)
+    #[arg(long, default_value = "300", env = "OGIS_LOGO_NEGATIVE_TTL_STATUS")]
+    pub negative_ttl_status_secs: u64,
+
+    /// Negative cache TTL in seconds for oversized, overly complex or non-image responses
+    #[arg(long, default_value = "600", env = "OGIS_LOGO_NEGATIVE_TTL_INVALID")]
+    pub negative_ttl_invalid_secs: u64,
+
+    /// Negative cache TTL in seconds for URLs blocked by SSRF protection
+    #[arg(long, default_value = "3600", env = "OGIS_LOGO_NEGATIVE_TTL_BLOCKED")]
+    pub negative_ttl_blocked_secs: u64,
+
     /// Maximum redirects to follow for logo URLs
     #[arg(long, default_value = "3", env = "OGIS_LOGO_MAX_REDIRECTS")]
     pub max_redirects: usize,
 
+    /// Retries for transient image fetch failures (connection errors, timeouts, 408/429/5xx)
+    #[arg(long, default_value = "2", env = "OGIS_IMAGE_MAX_RETRIES")]
+    pub max_retries: u32,
+
+    /// Base delay before retrying an image fetch in milliseconds, doubled per retry with jitter
+    #[arg(long, default_value = "200", env = "OGIS_IMAGE_RETRY_BACKOFF_MS")]
+    pub retry_backoff_ms: u64,
+
+    /// Consecutive failures after which fetches to a host are short-circuited (0 disables)
+    #[arg(long, default_value = "5", env = "OGIS_IMAGE_CIRCUIT_THRESHOLD")]
+    pub circuit_failure_threshold: u32,
+
+    /// How long fetches to a failing host are short-circuited, in seconds
+    #[arg(long, default_value = "30", env = "OGIS_IMAGE_CIRCUIT_OPEN_SECS")]
+    pub circuit_open_secs: u64,
+
     /// Allow HTTP (insecure) URLs for logo fetching (HTTPS only by default)
     #[arg(long, default_value = "false", env = "OGIS_ALLOW_HTTP")]
     pub allow_http: bool,
 
+    /// Only fetch images from these hosts (exact, `*.example.com`, or CIDR for resolved IPs)
+    #[arg(long, value_delimiter = ',', env = "OGIS_IMAGE_ALLOWED_HOSTS")]
+    pub allowed_hosts: Vec<String>,
+
+    /// Never fetch images from these hosts (exact, `*.example.com`, or CIDR for resolved IPs)
+    #[arg(long, value_delimiter = ',', env = "OGIS_IMAGE_DENIED_HOSTS")]
+    pub denied_hosts: Vec<String>,
+
+    /// Private networks images may still be fetched from, bypassing SSRF protection (CIDRs)
+    #[arg(long, value_delimiter = ',', env = "OGIS_IMAGE_SSRF_ALLOWED_NETWORKS")]
+    pub ssrf_allowed_networks: Vec<String>,
+
+    /// Drop private addresses of a host and connect to the rest, instead of rejecting the host
+    #[arg(long, default_value = "false", env = "OGIS_IMAGE_FILTER_PRIVATE_IPS")]
+    pub filter_private_ips: bool,
+
+    /// DNS servers for image hosts, as `ip` or `ip:port` (system configuration if empty)
+    #[arg(long, value_delimiter = ',', env = "OGIS_IMAGE_DNS_SERVERS")]
+    pub dns_servers: Vec<String>,
+
+    /// Static addresses for image hosts, as `host=ip` (repeat a host for several addresses)
+    #[arg(long, value_delimiter = ',', env = "OGIS_IMAGE_HOST_OVERRIDES")]
+    pub host_overrides: Vec<String>,
+
+    /// Egress proxy for image fetches (`http://`, `https://`, `socks5://` or `socks5h://` URL)
+    ///
+    /// The proxy resolves image hosts, so it must block private destinations itself, and CIDR
+    /// allowed/denied host patterns are rejected.
+    #[arg(long, env = "OGIS_IMAGE_PROXY")]
+    pub proxy: Option<String>,
+
+    /// User-Agent header sent with image fetches
+    #[arg(long, default_value = concat!("ogis/", env!("CARGO_PKG_VERSION")), env = "OGIS_IMAGE_USER_AGENT")]
+    pub user_agent: String,
+
+    /// Extra request headers for matching image hosts, as `host=Name:Value` (host may be `*.example.com`)
+    #[arg(long, value_delimiter = ',', env = "OGIS_IMAGE_HOST_HEADERS")]
+    pub host_headers: Vec<String>,
+
+    /// Directory of local images that can be referenced as `asset:<path>`
+    #[arg(long, env = "OGIS_ASSETS_DIR")]
+    pub assets_dir: Option<std::path::PathBuf>,
+
+    /// Serve image URLs from `<dir>/<host>/<path>` instead of the network (offline development)
+    ///
+    /// Only available in debug builds, as it bypasses the host policy and SSRF checks.
+    #[cfg(debug_assertions)]
+    #[arg(long, env = "OGIS_IMAGE_FIXTURES_DIR")]
+    pub fixtures_dir: Option<std::path::PathBuf>,
+
     /// Behavior when image URL fetch fails
     #[arg(long, default_value = "skip", env = "OGIS_IMAGE_FALLBACK")]
     pub fallback: ImageFallbackBehavior,
+
+    /// Fallback behavior for specific errors, as `kind=behavior` (e.g. `private-ip-blocked=error`)
+    ///
+    /// These take precedence over the `on_image_error` request parameter.
+    #[arg(long, value_delimiter = ',', env = "OGIS_IMAGE_FALLBACK_BY_ERROR")]
+    pub fallback_by_error: Vec<String>,
+
+    /// Image rendered by the `placeholder` fallback (URL, `asset:` path or `data:` URI)
+    #[arg(long, env = "OGIS_IMAGE_PLACEHOLDER")]
+    pub placeholder: Option<String>,
 }
 
 #[derive(Parser)]
@@ -77,6 +288,22 @@ pub struct Config {
     #[arg(long, default_value = "1000", env = "OGIS_MAX_INPUT_LENGTH")]
     pub max_input_length: usize,
 
+    /// Maximum length for `data:` URIs passed as image fields
+    #[arg(long, default_value = "1000000", env = "OGIS_MAX_DATA_URI_LENGTH")]
+    pub max_data_uri_length: usize,
+
+    /// Maximum request body size for multipart uploads (default: 12MB)
+    #[arg(long, default_value = "12582912", env = "OGIS_MAX_UPLOAD_SIZE")]
+    pub max_upload_size: usize,
+
+    /// Directory of SVG templates selectable with the `template` parameter, and their partials
+    #[arg(long, env = "OGIS_TEMPLATES_DIR")]
+    pub templates_dir: Option<std::path::PathBuf>,
+
+    /// Template rendered when the request does not select one
+    #[arg(long, default_value = "twilight", env = "OGIS_DEFAULT_TEMPLATE")]
+    pub default_template: String,
+
     #[command(flatten)]
     pub defaults: Defaults,
 
@@ -2,16 +2,22 @@ use quick_xml::Writer;
 use quick_xml::events::Event;
 use std::io::Cursor;
 
-use crate::generator::events::State;
+use crate::generator::events::{State, replacements};
 use crate::generator::utils::write_event;
 
 pub fn handle_default(
     e: Event,
     writer: &mut Writer<Cursor<Vec<u8>>>,
-    state: &State,
+    state: &mut State,
 ) -> Result<(), String> {
-    // Only write if we're not inside a skipped element
-    if !state.is_skipping() {
+    // Inside a list slot, collect the item template instead
+    if state.list.is_some() {
+        return replacements::list::capture(e.into_owned(), writer, state);
+    }
+
+    // Only write if we're not inside a skipped element or dropped slot placeholder
+    let hidden = state.slot.as_ref().is_some_and(|slot| slot.hides_content());
+    if !state.is_skipping() && !hidden {
         write_event(writer, e)?;
     }
 
@@ -3,6 +3,7 @@ use quick_xml::events::{BytesStart, Event};
 use std::io::Cursor;
 
 use crate::generator::events::{State, replacements};
+use crate::generator::template_params;
 use crate::generator::utils::write_event;
 
 /// Handles Event::Empty (self-closing tags like `<rect ... />`)
@@ -11,16 +12,41 @@ pub fn handle_empty(
     writer: &mut Writer<Cursor<Vec<u8>>>,
     state: &mut State,
 ) -> Result<(), String> {
+    // Inside a list slot, collect the item template instead
+    if state.list.is_some() {
+        return replacements::list::capture(Event::Empty(e.into_owned()), writer, state);
+    }
+
     // If we're already skipping, skip this element
     if state.is_skipping() {
         return Ok(());
     }
 
+    // Parameter declarations are template metadata, not content
+    if template_params::is_declaration(&e) {
+        return Ok(());
+    }
+
+    // Leave out elements whose conditions do not hold, with their content
+    if !replacements::condition::is_rendered(&e, state) {
+        return Ok(());
+    }
+
+    // Substitute template parameters into attribute values
+    let e = replacements::attribute::substitute_attributes(e, state)?;
+
     // If we're currently processing an image replacement, handle elements inside the group
-    if state.replacement_id.is_some() {
-        return replacements::image::handle_element_inside_image_group(&e, writer, state);
+    if state.slot.is_some()
+        && replacements::image::handle_element_inside_image_group(&e, true, writer, state)?
+    {
+        return Ok(());
     }
 
+    // Inline template assets, dropping the element if its asset is unavailable
+    let Some(e) = replacements::asset::resolve_asset_href(e, state) else {
+        return Ok(());
+    };
+
     // Write element as-is
     write_event(writer, Event::Empty(e))
 }
@@ -2,7 +2,7 @@ use quick_xml::Writer;
 use quick_xml::events::{BytesEnd, Event};
 use std::io::Cursor;
 
-use crate::generator::events::State;
+use crate::generator::events::{State, replacements};
 use crate::generator::utils::write_event;
 
 /// Handles Event::End (closing tags like `</g>`)
@@ -11,12 +11,26 @@ pub fn handle_end(
     writer: &mut Writer<Cursor<Vec<u8>>>,
     state: &mut State,
 ) -> Result<(), String> {
+    // Inside a list slot, collect the item template, or write the items at its end
+    if state.list.is_some() {
+        return replacements::list::capture(Event::End(e.into_owned()), writer, state);
+    }
+
     // If we're inside a skipped element, decrement depth but don't write the closing tag
     if state.is_skipping() {
         state.end_skip();
         return Ok(());
     }
 
+    // Inside an image slot, only close elements that were written, and the slot group itself
+    if let Some(slot) = state.slot.as_mut() {
+        match slot.open.pop() {
+            Some(false) => return Ok(()),
+            Some(true) => {}
+            None => state.slot = None,
+        }
+    }
+
     // Write closing tag as-is
     write_event(writer, Event::End(e))
 }
@@ -4,7 +4,8 @@ use std::io::Cursor;
 
 use crate::generator::events::{State, replacements};
 use crate::generator::strategies::apply_text_replacement;
-use crate::generator::utils::{get_id_from_element, write_event};
+use crate::generator::template_params;
+use crate::generator::utils::{get_slot_from_element, write_event};
 
 /// Handles Event::Start (opening tags like `<g id="logo">`)
 pub fn handle_start(
@@ -12,31 +13,70 @@ pub fn handle_start(
     writer: &mut Writer<Cursor<Vec<u8>>>,
     state: &mut State,
 ) -> Result<(), String> {
+    // Inside a list slot, collect the item template instead
+    if state.list.is_some() {
+        return replacements::list::capture(Event::Start(e.into_owned()), writer, state);
+    }
+
     // If we're already skipping, just increment depth and skip this element
     if state.is_skipping() {
         state.start_skip();
         return Ok(());
     }
 
+    // Parameter declarations are template metadata, not content
+    if template_params::is_declaration(&e) {
+        state.start_skip();
+        return Ok(());
+    }
+
+    // Leave out elements whose conditions do not hold, with their content
+    if !replacements::condition::is_rendered(&e, state) {
+        state.start_skip();
+        return Ok(());
+    }
+
+    // Substitute template parameters into attribute values
+    let e = replacements::attribute::substitute_attributes(e, state)?;
+
     // If we're currently processing an image replacement, handle elements inside the group
-    if state.replacement_id.is_some() {
-        return replacements::image::handle_element_inside_image_group(&e, writer, state);
+    if state.slot.is_some()
+        && replacements::image::handle_element_inside_image_group(&e, false, writer, state)?
+    {
+        return Ok(());
     }
 
-    // Check if this element has an id we want to replace
-    if let Some(id) = get_id_from_element(&e) {
-        // Try image replacement first (for group elements)
-        if replacements::image::try_start_image_replacement(&id, state) {
-            return Ok(());
-        }
+    // Collect the item template of list slots, to be repeated per item
+    if state.slot.is_none() && replacements::list::try_start_list(&e, writer, state)? {
+        return Ok(());
+    }
+
+    // Try image replacement first (for group elements)
+    if state.slot.is_none() && replacements::image::try_start_image_replacement(&e, writer, state)?
+    {
+        return Ok(());
+    }
 
-        // Try text replacement (needs bytes for now)
-        if apply_text_replacement(&e, id.as_bytes(), &state.text_replacements, writer)? {
+    // Check if this element is bound to a slot we want to replace
+    if let Some(slot) = get_slot_from_element(&e) {
+        // Try text replacement
+        if apply_text_replacement(&e, &slot, &state.text_replacements, writer)? {
             state.start_skip();
             return Ok(());
         }
     }
 
+    // Inline template assets, dropping the element if its asset is unavailable
+    let Some(e) = replacements::asset::resolve_asset_href(e, state) else {
+        state.start_skip();
+        return Ok(());
+    };
+
+    // Track written fallback content so its closing tag is written too
+    if let Some(slot) = state.slot.as_mut() {
+        slot.open.push(true);
+    }
+
     // Write element as-is
     write_event(writer, Event::Start(e))
 }
@@ -0,0 +1,36 @@
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use quick_xml::events::BytesStart;
+
+use crate::generator::events::State;
+
+/// Rewrite an `asset:` href on a template element to a data URI of the preloaded asset
+///
+/// Elements without an asset reference are returned unchanged. Returns None if the
+/// referenced asset could not be loaded, in which case the element should be dropped.
+pub fn resolve_asset_href<'a>(e: BytesStart<'a>, state: &State) -> Option<BytesStart<'a>> {
+    let has_asset = e
+        .attributes()
+        .filter_map(|a| a.ok())
+        .any(|attr| attr.key.local_name().as_ref() == b"href" && attr.value.starts_with(b"asset:"));
+    if !has_asset {
+        return Some(e);
+    }
+
+    let mut resolved = BytesStart::new(String::from_utf8_lossy(e.name().as_ref()).into_owned());
+    for attr in e.attributes().filter_map(|a| a.ok()) {
+        if attr.key.local_name().as_ref() == b"href" && attr.value.starts_with(b"asset:") {
+            let href = String::from_utf8_lossy(&attr.value);
+            let asset = state.assets.get(href.as_ref())?;
+            let data_uri = format!(
+                "data:{};base64,{}",
+                asset.mime_type,
+                BASE64.encode(&asset.bytes)
+            );
+            resolved.push_attribute((attr.key.as_ref(), data_uri.as_bytes()));
+        } else {
+            resolved.push_attribute(attr);
+        }
+    }
+
+    Some(resolved)
+}
@@ -0,0 +1,129 @@
+use quick_xml::escape::escape;
+use quick_xml::events::BytesStart;
+use quick_xml::events::attributes::Attribute;
+use quick_xml::name::QName;
+use std::collections::HashMap;
+
+use crate::generator::events::State;
+
+/// Prefix of attributes that set another attribute to a parameter, e.g. `data-ogis-attr-fill="accent"`
+const BINDING_PREFIX: &[u8] = b"data-ogis-attr-";
+
+/// Substitute template parameters into an element's attribute values
+///
+/// `{{name}}` placeholders are replaced anywhere in a value, and `data-ogis-attr-<attr>="name"`
+/// sets `<attr>` to the parameter's value, replacing any value written in the template.
+/// Elements without either are returned unchanged. Templates are checked for unknown
+/// parameters when they are loaded, so the errors here only guard against bugs.
+pub fn substitute_attributes<'a>(
+    e: BytesStart<'a>,
+    state: &State,
+) -> Result<BytesStart<'a>, String> {
+    let needs_substitution = e.attributes().filter_map(|a| a.ok()).any(|attr| {
+        attr.key.as_ref().starts_with(BINDING_PREFIX) || attr.value.windows(2).any(|w| w == b"{{")
+    });
+    if !needs_substitution {
+        return Ok(e);
+    }
+
+    // Attributes set from bindings, which take the place of the template's own values
+    let mut bound = Vec::new();
+    for attr in e.attributes().filter_map(|a| a.ok()) {
+        let Some(target) = attr.key.as_ref().strip_prefix(BINDING_PREFIX) else {
+            continue;
+        };
+        let name = String::from_utf8_lossy(&attr.value);
+        let value = state
+            .params
+            .get(name.trim())
+            .ok_or_else(|| format!("Unknown template parameter: {}", name))?;
+        bound.push((target.to_vec(), value.clone()));
+    }
+
+    let mut substituted = BytesStart::new(String::from_utf8_lossy(e.name().as_ref()).into_owned());
+    for attr in e.attributes().filter_map(|a| a.ok()) {
+        let key = attr.key.as_ref();
+        if key.starts_with(BINDING_PREFIX) || bound.iter().any(|(target, _)| target == key) {
+            continue;
+        }
+
+        if attr.value.windows(2).any(|w| w == b"{{") {
+            let value = attr
+                .unescape_value()
+                .map_err(|e| format!("Invalid attribute value: {}", e))?;
+            let value = interpolate(&value, &state.params)?;
+            substituted.push_attribute(escaped(key, &value));
+        } else {
+            substituted.push_attribute(attr);
+        }
+    }
+    for (target, value) in &bound {
+        substituted.push_attribute(escaped(target, value));
+    }
+
+    Ok(substituted)
+}
+
+/// Attribute with `value` escaped, as the template's own text around placeholders was unescaped
+fn escaped<'a>(key: &'a [u8], value: &'a str) -> Attribute<'a> {
+    Attribute {
+        key: QName(key),
+        value: escape(value).into_owned().into_bytes().into(),
+    }
+}
+
+/// Names of the parameters an element's attributes refer to, through placeholders or bindings
+pub fn referenced_params(e: &BytesStart) -> Result<Vec<String>, String> {
+    let mut names = Vec::new();
+    for attr in e.attributes() {
+        let attr = attr.map_err(|e| format!("Invalid attribute: {}", e))?;
+        if attr.key.as_ref().starts_with(BINDING_PREFIX) {
+            names.push(String::from_utf8_lossy(&attr.value).trim().to_string());
+        } else if attr.value.windows(2).any(|w| w == b"{{") {
+            let value = attr
+                .unescape_value()
+                .map_err(|e| format!("Invalid attribute value: {}", e))?;
+            for (start, end) in placeholders(&value)? {
+                names.push(value[start + 2..end - 2].trim().to_string());
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Replace every `{{name}}` in `value` with the parameter's value
+fn interpolate(value: &str, params: &HashMap<String, String>) -> Result<String, String> {
+    let mut result = String::with_capacity(value.len());
+    let mut copied = 0;
+
+    for (start, end) in placeholders(value)? {
+        let name = value[start + 2..end - 2].trim();
+        let param = params
+            .get(name)
+            .ok_or_else(|| format!("Unknown template parameter: {}", name))?;
+
+        result.push_str(&value[copied..start]);
+        result.push_str(param);
+        copied = end;
+    }
+    result.push_str(&value[copied..]);
+
+    Ok(result)
+}
+
+/// Byte ranges of the `{{name}}` placeholders in `value`, braces included
+fn placeholders(value: &str) -> Result<Vec<(usize, usize)>, String> {
+    let mut ranges = Vec::new();
+    let mut offset = 0;
+
+    while let Some(start) = value[offset..].find("{{") {
+        let start = offset + start;
+        let end = value[start..]
+            .find("}}")
+            .ok_or_else(|| format!("Unclosed template placeholder in: {}", value))?;
+        offset = start + end + 2;
+        ranges.push((start, offset));
+    }
+
+    Ok(ranges)
+}
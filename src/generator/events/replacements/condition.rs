@@ -0,0 +1,47 @@
+use quick_xml::events::BytesStart;
+
+use crate::generator::events::State;
+use crate::generator::utils::get_attr;
+
+/// Attribute keeping an element only when a value is set
+const IF_ATTR: &str = "data-ogis-if";
+
+/// Attribute keeping an element only when a value is not set
+const UNLESS_ATTR: &str = "data-ogis-unless";
+
+/// Check whether an element's `data-ogis-if` and `data-ogis-unless` conditions hold
+///
+/// Elements without conditions are always rendered.
+pub fn is_rendered(e: &BytesStart, state: &State) -> bool {
+    let holds = |attr| get_attr(e, attr).map(|condition| is_set(condition.trim(), state));
+
+    holds(IF_ATTR).unwrap_or(true) && !holds(UNLESS_ATTR).unwrap_or(false)
+}
+
+/// Evaluate a condition
+///
+/// A text slot is set when it has non-blank text, an image slot when it has an image, a
+/// list slot when it has items, and a template parameter unless it is a flag turned off.
+/// `name=value` compares a template parameter's value instead. Unknown names are never set.
+fn is_set(condition: &str, state: &State) -> bool {
+    if let Some((name, expected)) = condition.split_once('=') {
+        return state
+            .params
+            .get(name.trim())
+            .is_some_and(|value| value == expected.trim());
+    }
+
+    if let Some(text) = state.text_replacements.get(condition) {
+        return !text.trim().is_empty();
+    }
+    if let Some(image) = state.image_replacements.get(condition) {
+        return image.is_some();
+    }
+    if let Some(items) = state.list_replacements.get(condition) {
+        return !items.is_empty();
+    }
+    state
+        .params
+        .get(condition)
+        .is_some_and(|value| value != "false")
+}
@@ -1,70 +1,157 @@
 use quick_xml::Writer;
-use quick_xml::events::BytesStart;
+use quick_xml::events::{BytesStart, Event};
 use std::io::Cursor;
 
-use crate::generator::events::State;
+use crate::generator::events::{ImageSlot, State};
+use crate::generator::image_style::ImageStyle;
 use crate::generator::strategies::image_content;
+use crate::generator::utils::{get_attr, get_slot_from_element, write_event};
+
+/// Attribute marking slot content that is only rendered while the slot has no image
+const FALLBACK_ATTR: &[u8] = b"data-ogis-fallback";
+
+/// Attribute marking a group as an image slot, optionally naming its request parameter
+const IMAGE_ATTR: &str = "data-ogis-image";
+
+/// Names of the built-in image slots
+const BUILTIN_SLOT_NAMES: [&str; 3] = ["background", "logo", "image"];
+
+/// Prefix of slot names that declare additional image slots, e.g. `img_avatar`
+const GENERIC_SLOT_PREFIX: &str = "img_";
 
 /// Handle the rect element that defines image bounds for replacement
 ///
 /// When we encounter a <rect> inside a group marked for image replacement,
 /// this function reads the rect's attributes and creates an <image> element.
+/// Only the first rect places the image; the rect itself is never written.
 pub fn handle_rect_for_image_replacement(
     rect: &BytesStart,
     writer: &mut Writer<Cursor<Vec<u8>>>,
     state: &mut State,
 ) -> Result<(), String> {
-    let id = state.replacement_id.as_ref().unwrap();
+    let slot = state.slot.as_mut().unwrap();
+    if slot.placed {
+        return Ok(());
+    }
+    slot.placed = true;
 
     // Check if we have image data for this ID
-    if let Some(Some(image_replacement)) = state.image_replacements.get(id) {
+    let id = state.next_image_id();
+    let slot = state.slot.as_ref().unwrap();
+    if let Some(Some(image_replacement)) = state.image_replacements.get(&slot.name) {
+        // Requested style wins over the rect's attributes, then the group's
+        let style = image_replacement
+            .style
+            .clone()
+            .or(ImageStyle::from_element(rect))
+            .or(slot.style.clone());
+
         // Replace with image element using rect's positioning attributes
         image_content::replace(
             rect,
             &image_replacement.bytes,
             &image_replacement.mime_type,
+            image_replacement.size,
+            &style,
+            id,
             writer,
         )?;
     }
-    // If None or no entry, we remove the entire group (skip remaining children)
-
-    // Clear the replacement state and start skipping remaining group children
-    state.replacement_id = None;
-    state.start_skip();
+    // If None or no entry, the rect placeholder is simply dropped
 
     Ok(())
 }
 
-/// Handle elements encountered while inside an image replacement group
+/// Handle elements opening inside an image replacement group
 ///
-/// When we're waiting for a rect to define image bounds, this handles
-/// any child elements we encounter.
+/// The first `<rect>` defines the image bounds. Children marked `data-ogis-fallback` are
+/// kept (with their subtree) only when the slot has no image; any other placeholder content
+/// is removed. Returns false if the element should be processed like any other element,
+/// which is the case for fallback content that is being rendered.
 pub fn handle_element_inside_image_group(
     e: &BytesStart,
+    is_empty: bool,
     writer: &mut Writer<Cursor<Vec<u8>>>,
     state: &mut State,
-) -> Result<(), String> {
-    // Check if this is the rect we're waiting for
+) -> Result<bool, String> {
+    let has_image = state.slot_has_image();
+    let slot = state.slot.as_mut().unwrap();
+
+    // Everything inside rendered fallback content is regular template content
+    if slot.in_fallback() {
+        return Ok(false);
+    }
+
+    if is_fallback(e) {
+        if has_image {
+            if !is_empty {
+                state.start_skip();
+            }
+            return Ok(true);
+        }
+        return Ok(false);
+    }
+
     if is_rect_element(e) {
-        return handle_rect_for_image_replacement(e, writer, state);
+        handle_rect_for_image_replacement(e, writer, state)?;
+        if !is_empty {
+            state.start_skip();
+        }
+        return Ok(true);
     }
 
-    // Not a rect, skip this element (we're inside the group waiting for rect)
-    Ok(())
+    // Other placeholder elements are dropped, but still searched for a rect and fallbacks
+    if !is_empty {
+        slot.open.push(false);
+    }
+    Ok(true)
 }
 
-/// Try to start an image replacement for a group element with the given ID
+/// Try to start an image replacement for a group element
 ///
-/// Returns true if this element should be replaced with an image,
-/// which means we'll wait for a child rect element to define the bounds.
-pub fn try_start_image_replacement(id_str: &str, state: &mut State) -> bool {
-    if state.image_replacements.contains_key(id_str) {
-        // Start waiting for the rect child to define image bounds
-        state.replacement_id = Some(id_str.to_string());
-        true // Don't write the <g> element
-    } else {
-        false
+/// Returns true if this element is an image slot. The group itself is written so its
+/// attributes still apply; its children are handled by `handle_element_inside_image_group`.
+pub fn try_start_image_replacement(
+    e: &BytesStart,
+    writer: &mut Writer<Cursor<Vec<u8>>>,
+    state: &mut State,
+) -> Result<bool, String> {
+    let Some(name) = image_slot_name(e) else {
+        return Ok(false);
+    };
+    if !state.image_replacements.contains_key(&name) {
+        return Ok(false);
     }
+
+    write_event(writer, Event::Start(e.borrow()))?;
+    state.slot = Some(ImageSlot::new(&name, ImageStyle::from_element(e)));
+    Ok(true)
+}
+
+/// Name of the image slot an element declares, which is also its request parameter
+///
+/// Slots are groups bound (by `data-ogis-slot` or an `ogis_*` ID) to the built-in `logo`,
+/// `image` and `background` slots or to any `img_*` slot, and groups with a
+/// `data-ogis-image` attribute. That attribute's value names the slot if set.
+pub fn image_slot_name(e: &BytesStart) -> Option<String> {
+    if let Ok(name) = get_attr(e, IMAGE_ATTR) {
+        let name = name.trim();
+        if !name.is_empty() {
+            return Some(name.to_string());
+        }
+        return get_slot_from_element(e);
+    }
+
+    get_slot_from_element(e).filter(|name| {
+        BUILTIN_SLOT_NAMES.contains(&name.as_str()) || name.starts_with(GENERIC_SLOT_PREFIX)
+    })
+}
+
+/// Check if an element is slot fallback content
+pub fn is_fallback(e: &BytesStart) -> bool {
+    e.attributes()
+        .filter_map(|a| a.ok())
+        .any(|attr| attr.key.as_ref() == FALLBACK_ATTR)
 }
 
 /// Check if an element is a rect
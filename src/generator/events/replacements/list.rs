@@ -0,0 +1,246 @@
+use quick_xml::Writer;
+use quick_xml::events::{BytesEnd, BytesStart, Event};
+use std::collections::HashMap;
+use std::io::Cursor;
+
+use crate::generator::events::{
+    ListCapture, State, handle_default, handle_empty, handle_end, handle_start,
+};
+use crate::generator::text_metrics::{FontSpec, text_width};
+use crate::generator::utils::{get_attr, get_slot_from_element, write_event};
+
+/// Attribute marking an element as a list slot and naming its request parameter
+const LIST_ATTR: &str = "data-ogis-list";
+
+/// Text slot filled with the current item inside a list's item template
+const ITEM_SLOT: &str = "item";
+
+/// Parameter holding the current item's measured width, e.g. `width="{{item_width}}"`
+pub const ITEM_WIDTH_PARAM: &str = "item_width";
+
+/// How the items of a list slot are laid out
+///
+/// Read from the list element: `data-ogis-max` caps the number of items, `data-ogis-gap`
+/// spaces items (and rows), `data-ogis-padding` is added on both sides of the item text
+/// to get the item width, and `data-ogis-width` wraps items onto new rows once a row
+/// would grow wider. Rows are `data-ogis-row-height` apart, defaulting to the height of
+/// the item template's first rect.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ListLayout {
+    pub max: Option<usize>,
+    pub gap: f32,
+    pub padding: f32,
+    pub width: Option<f32>,
+    pub row_height: Option<f32>,
+}
+
+impl ListLayout {
+    pub fn from_element(e: &BytesStart) -> Result<Self, String> {
+        let number = |attr: &str| -> Result<Option<f32>, String> {
+            get_attr(e, attr)
+                .ok()
+                .map(|v| {
+                    v.trim()
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|n| n.is_finite() && *n >= 0.0)
+                        .ok_or_else(|| format!("Invalid {} on list slot: {}", attr, v))
+                })
+                .transpose()
+        };
+
+        Ok(Self {
+            max: number("data-ogis-max")?.map(|max| max as usize),
+            gap: number("data-ogis-gap")?.unwrap_or(0.0),
+            padding: number("data-ogis-padding")?.unwrap_or(0.0),
+            width: number("data-ogis-width")?,
+            row_height: number("data-ogis-row-height")?,
+        })
+    }
+}
+
+/// Get the name of the list slot an element declares, if any
+pub fn list_slot_name(e: &BytesStart) -> Option<String> {
+    let name = get_attr(e, LIST_ATTR).ok()?;
+    let name = name.trim();
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Start collecting a list slot's item template if the element declares one
+///
+/// The list element itself is written as-is; its children are held back until its
+/// closing tag, then written once per item. Returns Ok(true) if the element was handled.
+pub fn try_start_list(
+    e: &BytesStart,
+    writer: &mut Writer<Cursor<Vec<u8>>>,
+    state: &mut State,
+) -> Result<bool, String> {
+    let Some(name) = list_slot_name(e) else {
+        return Ok(false);
+    };
+
+    state.list = Some(ListCapture {
+        name,
+        layout: ListLayout::from_element(e)?,
+        element: e.clone().into_owned(),
+        events: Vec::new(),
+        depth: 0,
+    });
+    write_event(writer, Event::Start(e.clone()))?;
+    Ok(true)
+}
+
+/// Collect an event of the item template being captured
+///
+/// The list element's closing tag ends the capture and writes out the items.
+pub fn capture(
+    event: Event<'static>,
+    writer: &mut Writer<Cursor<Vec<u8>>>,
+    state: &mut State,
+) -> Result<(), String> {
+    let list = state.list.as_mut().unwrap();
+    match &event {
+        Event::Start(_) => list.depth += 1,
+        Event::End(_) if list.depth == 0 => {
+            let Event::End(end) = event else {
+                unreachable!()
+            };
+            return finish_list(end, writer, state);
+        }
+        Event::End(_) => list.depth -= 1,
+        _ => {}
+    }
+    list.events.push(event);
+    Ok(())
+}
+
+/// Write the captured item template once per item, then close the list element
+///
+/// Each item is wrapped in a group translated to its place in the layout. Items are
+/// rendered like any other template content, with the `item` text slot and the
+/// `item_width` parameter set for the item.
+fn finish_list(
+    end: BytesEnd<'static>,
+    writer: &mut Writer<Cursor<Vec<u8>>>,
+    state: &mut State,
+) -> Result<(), String> {
+    let list = state.list.take().unwrap();
+    let mut items = state
+        .list_replacements
+        .get(&list.name)
+        .cloned()
+        .unwrap_or_default();
+    if let Some(max) = list.layout.max {
+        items.truncate(max);
+    }
+
+    let (font, first_rect_height) = scan_item_template(&list);
+    let row_height = list
+        .layout
+        .row_height
+        .or(first_rect_height)
+        .unwrap_or(font.size);
+
+    let (mut x, mut y) = (0.0, 0.0);
+    for item in items {
+        let width = text_width(&state.fonts, &item, &font) + 2.0 * list.layout.padding;
+
+        // Wrap onto the next row, unless the item would not fit on any row
+        if let Some(max_width) = list.layout.width
+            && x > 0.0
+            && x + width > max_width
+        {
+            x = 0.0;
+            y += row_height + list.layout.gap;
+        }
+
+        let mut group = BytesStart::new("g");
+        group.push_attribute(("transform", format!("translate({}, {})", x, y).as_str()));
+        write_event(writer, Event::Start(group.clone()))?;
+
+        let previous_text = state.text_replacements.insert(ITEM_SLOT.to_string(), item);
+        let previous_width = state
+            .params
+            .insert(ITEM_WIDTH_PARAM.to_string(), width.to_string());
+
+        let replayed = replay(&list.events, writer, state);
+
+        restore(&mut state.text_replacements, ITEM_SLOT, previous_text);
+        restore(&mut state.params, ITEM_WIDTH_PARAM, previous_width);
+        replayed?;
+
+        write_event(writer, Event::End(group.to_end()))?;
+        x += width + list.layout.gap;
+    }
+
+    write_event(writer, Event::End(end))
+}
+
+/// Run captured events through the regular handlers
+fn replay(
+    events: &[Event<'static>],
+    writer: &mut Writer<Cursor<Vec<u8>>>,
+    state: &mut State,
+) -> Result<(), String> {
+    for event in events {
+        match event.clone() {
+            Event::Start(e) => handle_start(e, writer, state)?,
+            Event::Empty(e) => handle_empty(e, writer, state)?,
+            Event::End(e) => handle_end(e, writer, state)?,
+            e => handle_default(e, writer, state)?,
+        }
+    }
+    Ok(())
+}
+
+/// Put back a value an item shadowed, e.g. for a list nested in another list's items
+fn restore(map: &mut HashMap<String, String>, key: &str, previous: Option<String>) {
+    match previous {
+        Some(value) => map.insert(key.to_string(), value),
+        None => map.remove(key),
+    };
+}
+
+/// Find the font of the item text and the height of the item template's first rect
+///
+/// Font attributes are inherited from the list element and the item text's ancestors
+/// within the item template.
+fn scan_item_template(list: &ListCapture) -> (FontSpec, Option<f32>) {
+    let mut fonts = vec![font_of(&list.element, FontSpec::default())];
+    let mut item_font = None;
+    let mut rect_height = None;
+
+    for event in &list.events {
+        match event {
+            Event::Start(e) | Event::Empty(e) => {
+                let font = font_of(e, fonts.last().cloned().unwrap_or_default());
+                if item_font.is_none() && get_slot_from_element(e).as_deref() == Some(ITEM_SLOT) {
+                    item_font = Some(font.clone());
+                }
+                if rect_height.is_none() && e.name().as_ref() == b"rect" {
+                    rect_height = get_attr(e, "height")
+                        .ok()
+                        .and_then(|h| h.trim().parse::<f32>().ok());
+                }
+                if matches!(event, Event::Start(_)) {
+                    fonts.push(font);
+                }
+            }
+            Event::End(_) => {
+                fonts.pop();
+            }
+            _ => {}
+        }
+    }
+
+    (item_font.unwrap_or_else(|| fonts[0].clone()), rect_height)
+}
+
+/// Apply an element's font attributes on top of the inherited font
+fn font_of(e: &BytesStart, mut font: FontSpec) -> FontSpec {
+    for attr in e.attributes().filter_map(|a| a.ok()) {
+        let key = String::from_utf8_lossy(attr.key.as_ref());
+        font.set(&key, &String::from_utf8_lossy(&attr.value));
+    }
+    font
+}
@@ -1 +1,5 @@
+pub mod asset;
+pub mod attribute;
+pub mod condition;
 pub mod image;
+pub mod list;
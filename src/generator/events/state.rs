@@ -1,8 +1,73 @@
+use quick_xml::events::{BytesStart, Event};
 use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::generator::events::replacements::list::ListLayout;
+use crate::generator::image_style::ImageStyle;
+use crate::generator::text_metrics::Fonts;
 
 pub struct ImageReplacement {
     pub bytes: Vec<u8>,
     pub mime_type: String,
+    /// Intrinsic width and height, if known
+    pub size: Option<(f32, f32)>,
+    /// Requested presentation, taking precedence over the template's
+    pub style: ImageStyle,
+}
+
+/// An image slot group currently being processed
+pub struct ImageSlot {
+    /// Slot name, which keys its image in `State::image_replacements`
+    pub name: String,
+
+    /// Whether the bounds rect has been reached (and the image placed, if any)
+    pub placed: bool,
+
+    /// Presentation set on the slot group itself
+    pub style: ImageStyle,
+
+    /// Elements currently open inside the slot, and whether each one was written
+    /// Only fallback content is written; other placeholder elements are dropped
+    pub open: Vec<bool>,
+}
+
+impl ImageSlot {
+    pub fn new(name: &str, style: ImageStyle) -> Self {
+        Self {
+            name: name.to_string(),
+            placed: false,
+            style,
+            open: Vec::new(),
+        }
+    }
+
+    /// Check if we're inside fallback content that is being rendered
+    pub fn in_fallback(&self) -> bool {
+        self.open.contains(&true)
+    }
+
+    /// Check if we're inside a dropped placeholder element
+    pub fn hides_content(&self) -> bool {
+        self.open.last() == Some(&false)
+    }
+}
+
+/// A list slot whose item template is being collected
+pub struct ListCapture {
+    /// Slot name, which keys its items in `State::list_replacements`
+    pub name: String,
+
+    /// Spacing, wrapping and count limit set on the list element
+    pub layout: ListLayout,
+
+    /// The list element's own attributes, from which the item text inherits its font
+    pub element: BytesStart<'static>,
+
+    /// Events of the item template, replayed once per item
+    pub events: Vec<Event<'static>>,
+
+    /// How deep we are inside the item template
+    pub depth: usize,
 }
 
 /// State for tracking SVG processing
@@ -12,28 +77,58 @@ pub struct State {
     /// >0 means we're inside a skipped element (and possibly nested children)
     pub skip_depth: usize,
 
-    /// The ID of the image group currently being replaced
-    /// When set, we're waiting for a <rect> child to define image bounds
-    pub replacement_id: Option<String>,
+    /// The image group currently being replaced
+    /// When set, its first <rect> child defines the image bounds
+    pub slot: Option<ImageSlot>,
+
+    /// The list slot currently being collected
+    /// When set, every event up to the list element's closing tag belongs to its item template
+    pub list: Option<ListCapture>,
 
-    /// Map of element IDs to their replacement text values
+    /// Map of slot names to their replacement text values
     pub text_replacements: HashMap<String, String>,
 
-    /// Map of element IDs to their replacement images
+    /// Map of image slot names to their replacement images
     /// None means remove the element entirely, Some means replace with image
     pub image_replacements: HashMap<String, Option<ImageReplacement>>,
+
+    /// Map of list slot names to their items
+    pub list_replacements: HashMap<String, Vec<String>>,
+
+    /// Validated template parameter values by name, substituted into attribute values
+    pub params: HashMap<String, String>,
+
+    /// Map of `asset:` hrefs used by the template to their loaded images
+    pub assets: HashMap<String, ImageReplacement>,
+
+    /// Fonts the SVG will be rendered with, used to measure list items
+    pub fonts: Arc<Fonts>,
+
+    /// Number of images placed so far, used to give their clip paths, filters and overlays
+    /// IDs that are unique within the document
+    pub placed_images: usize,
 }
 
 impl State {
     pub fn new(
         text_replacements: HashMap<String, String>,
         image_replacements: HashMap<String, Option<ImageReplacement>>,
+        list_replacements: HashMap<String, Vec<String>>,
+        params: HashMap<String, String>,
+        assets: HashMap<String, ImageReplacement>,
+        fonts: Arc<Fonts>,
     ) -> Self {
         Self {
             skip_depth: 0,
             text_replacements,
             image_replacements,
-            replacement_id: None,
+            list_replacements,
+            params,
+            assets,
+            fonts,
+            slot: None,
+            list: None,
+            placed_images: 0,
         }
     }
 
@@ -47,6 +142,19 @@ impl State {
         self.skip_depth += 1;
     }
 
+    /// Check if the current image slot has an image to place
+    pub fn slot_has_image(&self) -> bool {
+        self.slot
+            .as_ref()
+            .is_some_and(|slot| matches!(self.image_replacements.get(&slot.name), Some(Some(_))))
+    }
+
+    /// Get an ID suffix for the defs of the next placed image
+    pub fn next_image_id(&mut self) -> usize {
+        self.placed_images += 1;
+        self.placed_images
+    }
+
     /// Stop skipping (decrement depth)
     pub fn end_skip(&mut self) {
         if self.skip_depth > 0 {
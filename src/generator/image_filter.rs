@@ -0,0 +1,226 @@
+use quick_xml::Writer;
+use quick_xml::events::{BytesStart, Event};
+use std::io::Cursor;
+use std::str::FromStr;
+use svgtypes::Color;
+
+use super::utils::{split_top_level, write_event};
+
+/// Default strength of `tint(color)` when no amount is given
+const DEFAULT_TINT_AMOUNT: f32 = 0.5;
+
+/// Maximum number of filters in one list, each adding a filter primitive to render
+const MAX_FILTERS: usize = 8;
+
+/// A single filter applied to an embedded image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFilter {
+    /// Desaturate, from 0 (unchanged) to 1 (fully gray)
+    Grayscale(f32),
+    /// Gaussian blur with the given standard deviation in template units
+    Blur(f32),
+    /// Multiply color channels, below 1 darkens and above 1 brightens
+    Brightness(f32),
+    /// Overall opacity, from 0 (invisible) to 1 (opaque)
+    Opacity(f32),
+    /// Blend a color over the image with the given strength (0 to 1)
+    Tint(Color, f32),
+    /// Map shadows to the first color and highlights to the second
+    Duotone(Color, Color),
+}
+
+/// Ordered list of filters, written like CSS: `grayscale(1) brightness(0.6) tint(#1a1a2e, 0.4)`
+///
+/// Colors may be written in any CSS form, including `rgb(...)`. At most `MAX_FILTERS` filters
+/// are accepted.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImageFilters(pub Vec<ImageFilter>);
+
+impl FromStr for ImageFilters {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filters = Vec::new();
+        let mut rest = s.trim();
+
+        while !rest.is_empty() {
+            let (name, after) = rest
+                .split_once('(')
+                .ok_or_else(|| format!("invalid filter (expected name(args)): {}", rest))?;
+            let close = closing_paren(after)
+                .ok_or_else(|| format!("unclosed filter arguments: {}", rest))?;
+
+            let args = split_top_level(&after[..close]);
+            filters.push(parse_filter(name.trim(), &args)?);
+            if filters.len() > MAX_FILTERS {
+                return Err(format!("too many filters (at most {})", MAX_FILTERS));
+            }
+            rest = after[close + 1..].trim_start();
+        }
+
+        Ok(Self(filters))
+    }
+}
+
+/// Position of the parenthesis closing the arguments `args` starts with, skipping nested
+/// ones such as in `rgb(...)`
+fn closing_paren(args: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in args.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Some(i),
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parse_filter(name: &str, args: &[&str]) -> Result<ImageFilter, String> {
+    let number = |i: usize| -> Result<f32, String> {
+        let arg = args.get(i).copied().unwrap_or_default();
+        arg.parse::<f32>()
+            .ok()
+            .filter(|v| v.is_finite() && *v >= 0.0)
+            .ok_or_else(|| format!("invalid {} argument: {}", name, arg))
+    };
+    let fraction = |i: usize| number(i).map(|v| v.min(1.0));
+    let color = |i: usize| -> Result<Color, String> {
+        let arg = args.get(i).copied().unwrap_or_default();
+        Color::from_str(arg).map_err(|_| format!("invalid {} color: {}", name, arg))
+    };
+
+    let filter = match name.to_ascii_lowercase().as_str() {
+        "grayscale" => ImageFilter::Grayscale(fraction(0)?),
+        "blur" => ImageFilter::Blur(number(0)?),
+        "brightness" => ImageFilter::Brightness(number(0)?),
+        "opacity" => ImageFilter::Opacity(fraction(0)?),
+        "tint" if args.len() > 1 => ImageFilter::Tint(color(0)?, fraction(1)?),
+        "tint" => ImageFilter::Tint(color(0)?, DEFAULT_TINT_AMOUNT),
+        "duotone" => ImageFilter::Duotone(color(0)?, color(1)?),
+        other => return Err(format!("unknown filter: {}", other)),
+    };
+    Ok(filter)
+}
+
+impl ImageFilters {
+    /// Combined opacity, applied on the image element rather than in the filter
+    pub fn opacity(&self) -> Option<f32> {
+        self.0
+            .iter()
+            .filter_map(|f| match f {
+                ImageFilter::Opacity(o) => Some(*o),
+                _ => None,
+            })
+            .reduce(|a, b| a * b)
+    }
+
+    /// Whether an SVG `<filter>` element is needed
+    pub fn needs_filter_element(&self) -> bool {
+        self.0.iter().any(|f| !matches!(f, ImageFilter::Opacity(_)))
+    }
+
+    /// Write a `<filter>` element with the given ID chaining all filter primitives
+    pub fn write_filter(
+        &self,
+        id: &str,
+        writer: &mut Writer<Cursor<Vec<u8>>>,
+    ) -> Result<(), String> {
+        let mut filter = BytesStart::new("filter");
+        filter.push_attribute(("id", id));
+        filter.push_attribute(("color-interpolation-filters", "sRGB"));
+        write_event(writer, Event::Start(filter.clone()))?;
+
+        let mut previous = "SourceGraphic".to_string();
+        for (i, image_filter) in self.0.iter().enumerate() {
+            let result = format!("f{}", i);
+            match *image_filter {
+                ImageFilter::Grayscale(amount) => {
+                    let saturate = (1.0 - amount).to_string();
+                    let matrix = primitive("feColorMatrix", &previous, &result)
+                        .with_attributes([("type", "saturate"), ("values", saturate.as_str())]);
+                    write_event(writer, Event::Empty(matrix))?;
+                }
+                ImageFilter::Blur(deviation) => {
+                    let deviation = deviation.to_string();
+                    let blur = primitive("feGaussianBlur", &previous, &result)
+                        .with_attributes([("stdDeviation", deviation.as_str())]);
+                    write_event(writer, Event::Empty(blur))?;
+                }
+                ImageFilter::Brightness(slope) => {
+                    let slope = slope.to_string();
+                    let transfer = primitive("feComponentTransfer", &previous, &result);
+                    write_event(writer, Event::Start(transfer.clone()))?;
+                    for channel in ["feFuncR", "feFuncG", "feFuncB"] {
+                        let func = BytesStart::new(channel)
+                            .with_attributes([("type", "linear"), ("slope", slope.as_str())]);
+                        write_event(writer, Event::Empty(func))?;
+                    }
+                    write_event(writer, Event::End(transfer.to_end()))?;
+                }
+                ImageFilter::Tint(color, amount) => {
+                    // Flood the color, then paint it over the image only where the image is opaque
+                    let flood_result = format!("{}_flood", result);
+                    let amount = amount.to_string();
+                    let flood = BytesStart::new("feFlood").with_attributes([
+                        ("flood-color", rgb(color).as_str()),
+                        ("flood-opacity", amount.as_str()),
+                        ("result", flood_result.as_str()),
+                    ]);
+                    write_event(writer, Event::Empty(flood))?;
+
+                    let composite = primitive("feComposite", &flood_result, &result)
+                        .with_attributes([("in2", previous.as_str()), ("operator", "atop")]);
+                    write_event(writer, Event::Empty(composite))?;
+                }
+                ImageFilter::Duotone(dark, light) => {
+                    // Reduce to luminance, then map it onto the gradient between the two colors
+                    let gray_result = format!("{}_gray", result);
+                    let luminance = BytesStart::new("feColorMatrix").with_attributes([
+                        ("in", previous.as_str()),
+                        ("type", "matrix"),
+                        (
+                            "values",
+                            "0.2126 0.7152 0.0722 0 0 \
+                             0.2126 0.7152 0.0722 0 0 \
+                             0.2126 0.7152 0.0722 0 0 \
+                             0 0 0 1 0",
+                        ),
+                        ("result", gray_result.as_str()),
+                    ]);
+                    write_event(writer, Event::Empty(luminance))?;
+
+                    let transfer = primitive("feComponentTransfer", &gray_result, &result);
+                    write_event(writer, Event::Start(transfer.clone()))?;
+                    let channels = [
+                        ("feFuncR", dark.red, light.red),
+                        ("feFuncG", dark.green, light.green),
+                        ("feFuncB", dark.blue, light.blue),
+                    ];
+                    for (channel, from, to) in channels {
+                        let table = format!("{} {}", from as f32 / 255.0, to as f32 / 255.0);
+                        let func = BytesStart::new(channel)
+                            .with_attributes([("type", "table"), ("tableValues", table.as_str())]);
+                        write_event(writer, Event::Empty(func))?;
+                    }
+                    write_event(writer, Event::End(transfer.to_end()))?;
+                }
+                // Applied as the image element's opacity
+                ImageFilter::Opacity(_) => continue,
+            }
+            previous = result;
+        }
+
+        write_event(writer, Event::End(filter.to_end()))
+    }
+}
+
+/// Filter primitive reading from `input` and storing its output as `result`
+fn primitive(name: &str, input: &str, result: &str) -> BytesStart<'static> {
+    BytesStart::new(name.to_string()).with_attributes([("in", input), ("result", result)])
+}
+
+fn rgb(color: Color) -> String {
+    format!("rgb({},{},{})", color.red, color.green, color.blue)
+}
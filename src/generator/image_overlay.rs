@@ -0,0 +1,122 @@
+use quick_xml::Writer;
+use quick_xml::events::{BytesStart, Event};
+use std::io::Cursor;
+use std::str::FromStr;
+use svgtypes::Color;
+
+use super::utils::{split_top_level, write_event};
+
+/// Layer painted over an embedded image, below the rest of the template
+///
+/// Written as a color (`#0f0f23cc`, `rgba(0, 0, 0, 0.5)`), a CSS-like
+/// `linear-gradient([angle,] color, color, ...)` with evenly spaced stops, or `none`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ImageOverlay {
+    /// No overlay, e.g. to disable the template's
+    None,
+    /// A single color, usually translucent
+    Solid(Color),
+    /// Colors spread along a direction, in degrees clockwise from "to top"
+    Linear { angle: f32, stops: Vec<Color> },
+}
+
+impl FromStr for ImageOverlay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("none") {
+            return Ok(Self::None);
+        }
+
+        let Some(args) = s
+            .strip_prefix("linear-gradient(")
+            .and_then(|rest| rest.strip_suffix(')'))
+        else {
+            let color = Color::from_str(s).map_err(|_| format!("invalid overlay color: {}", s))?;
+            return Ok(Self::Solid(color));
+        };
+
+        let mut args = split_top_level(args);
+        let angle = match args.first().and_then(|a| a.strip_suffix("deg")) {
+            Some(degrees) => {
+                let angle = degrees
+                    .trim()
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|a| a.is_finite())
+                    .ok_or_else(|| format!("invalid overlay angle: {}", args[0]))?;
+                args.remove(0);
+                angle
+            }
+            // Top to bottom, like CSS
+            None => 180.0,
+        };
+
+        let stops = args
+            .iter()
+            .map(|c| Color::from_str(c).map_err(|_| format!("invalid overlay color: {}", c)))
+            .collect::<Result<Vec<_>, _>>()?;
+        if stops.len() < 2 {
+            return Err(format!("overlay gradient needs at least two colors: {}", s));
+        }
+
+        Ok(Self::Linear { angle, stops })
+    }
+}
+
+impl ImageOverlay {
+    /// Write the gradient definition an overlay refers to, if it needs one
+    pub fn write_defs(&self, id: &str, writer: &mut Writer<Cursor<Vec<u8>>>) -> Result<(), String> {
+        let Self::Linear { angle, stops } = self else {
+            return Ok(());
+        };
+
+        // Direction vector in bounding box units, centered on the box
+        let (sin, cos) = angle.to_radians().sin_cos();
+        let (dx, dy) = (sin / 2.0, -cos / 2.0);
+        let mut gradient = BytesStart::new("linearGradient");
+        gradient.push_attribute(("id", id));
+        gradient.push_attribute(("x1", (0.5 - dx).to_string().as_str()));
+        gradient.push_attribute(("y1", (0.5 - dy).to_string().as_str()));
+        gradient.push_attribute(("x2", (0.5 + dx).to_string().as_str()));
+        gradient.push_attribute(("y2", (0.5 + dy).to_string().as_str()));
+        write_event(writer, Event::Start(gradient.clone()))?;
+
+        for (i, color) in stops.iter().enumerate() {
+            let offset = i as f32 / (stops.len() - 1) as f32;
+            let mut stop = BytesStart::new("stop");
+            stop.push_attribute(("offset", offset.to_string().as_str()));
+            stop.push_attribute(("stop-color", rgb(*color).as_str()));
+            stop.push_attribute(("stop-opacity", alpha(*color).as_str()));
+            write_event(writer, Event::Empty(stop))?;
+        }
+
+        write_event(writer, Event::End(gradient.to_end()))
+    }
+
+    /// Paint `shape` with the overlay, using the gradient written under `id`
+    ///
+    /// Returns None for `none`, in which case nothing should be drawn.
+    pub fn fill(&self, mut shape: BytesStart<'static>, id: &str) -> Option<BytesStart<'static>> {
+        match self {
+            Self::None => return None,
+            Self::Solid(color) => {
+                shape.push_attribute(("fill", rgb(*color).as_str()));
+                shape.push_attribute(("fill-opacity", alpha(*color).as_str()));
+            }
+            Self::Linear { .. } => {
+                shape.push_attribute(("fill", format!("url(#{})", id).as_str()));
+            }
+        }
+        Some(shape)
+    }
+}
+
+fn rgb(color: Color) -> String {
+    format!("rgb({},{},{})", color.red, color.green, color.blue)
+}
+
+fn alpha(color: Color) -> String {
+    (color.alpha as f32 / 255.0).to_string()
+}
@@ -0,0 +1,172 @@
+use quick_xml::events::BytesStart;
+use serde::Deserialize;
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+use super::image_filter::ImageFilters;
+use super::image_overlay::ImageOverlay;
+use super::utils::get_attr;
+use crate::image::ValidatedImage;
+
+/// How an image is sized to its slot
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFit {
+    /// Scale to fit inside the slot, keeping the aspect ratio
+    #[default]
+    Contain,
+    /// Scale to cover the slot, keeping the aspect ratio and cropping around the focal point
+    Cover,
+    /// Stretch to exactly the slot size
+    Fill,
+}
+
+impl FromStr for ImageFit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "contain" => Ok(Self::Contain),
+            "cover" => Ok(Self::Cover),
+            "fill" => Ok(Self::Fill),
+            other => Err(format!("unknown fit mode: {}", other)),
+        }
+    }
+}
+
+/// Shape an image is clipped to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MaskShape {
+    /// Square corners
+    Rect,
+    /// Rounded corners, using the slot's `rx` unless a radius is given
+    #[default]
+    Rounded,
+    /// Circle centered in the slot
+    Circle,
+    /// Superellipse filling the slot
+    Squircle,
+}
+
+impl FromStr for MaskShape {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "rect" => Ok(Self::Rect),
+            "rounded" => Ok(Self::Rounded),
+            "circle" => Ok(Self::Circle),
+            "squircle" => Ok(Self::Squircle),
+            other => Err(format!("unknown mask shape: {}", other)),
+        }
+    }
+}
+
+/// Point of the image kept in view when it is cropped or letterboxed
+///
+/// Coordinates are fractions of the image size, `0,0` being the top left corner.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FocalPoint {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Default for FocalPoint {
+    fn default() -> Self {
+        Self { x: 0.5, y: 0.5 }
+    }
+}
+
+impl FromStr for FocalPoint {
+    type Err = String;
+
+    /// Parse `x,y` with both coordinates between 0 and 1
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid focal point (expected x,y between 0 and 1): {}", s);
+        let (x, y) = s.split_once(',').ok_or_else(invalid)?;
+        let parse = |v: &str| {
+            v.trim()
+                .parse::<f32>()
+                .ok()
+                .filter(|v| (0.0..=1.0).contains(v))
+                .ok_or_else(invalid)
+        };
+
+        Ok(Self {
+            x: parse(x)?,
+            y: parse(y)?,
+        })
+    }
+}
+
+/// Presentation of an image inside its slot
+///
+/// Unset fields fall back to the template's `data-ogis-*` attributes, then to the defaults
+/// (`contain`, centered, `rounded` with the slot's `rx`, unfiltered, no overlay).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImageStyle {
+    pub fit: Option<ImageFit>,
+    pub focus: Option<FocalPoint>,
+    pub mask: Option<MaskShape>,
+    pub radius: Option<f32>,
+    pub filter: Option<ImageFilters>,
+    pub overlay: Option<ImageOverlay>,
+}
+
+impl ImageStyle {
+    /// Read `data-ogis-fit`, `data-ogis-focus`, `data-ogis-mask`, `data-ogis-radius`,
+    /// `data-ogis-filter` and `data-ogis-overlay`
+    ///
+    /// Invalid values are ignored with a warning so a template typo does not break rendering.
+    pub fn from_element(e: &BytesStart) -> Self {
+        fn attr<T: FromStr<Err = String>>(e: &BytesStart, name: &str) -> Option<T> {
+            let value = get_attr(e, name).ok()?;
+            value
+                .parse()
+                .inspect_err(|err| tracing::warn!("Ignoring template attribute {}: {}", name, err))
+                .ok()
+        }
+
+        Self {
+            fit: attr(e, "data-ogis-fit"),
+            focus: attr(e, "data-ogis-focus"),
+            mask: attr(e, "data-ogis-mask"),
+            radius: get_attr(e, "data-ogis-radius").ok().and_then(|r| {
+                parse_radius(&r)
+                    .inspect_err(|err| {
+                        tracing::warn!("Ignoring template attribute data-ogis-radius: {}", err)
+                    })
+                    .ok()
+            }),
+            filter: attr(e, "data-ogis-filter"),
+            overlay: attr(e, "data-ogis-overlay"),
+        }
+    }
+
+    /// Fill unset fields from `fallback`
+    pub fn or(self, fallback: Self) -> Self {
+        Self {
+            fit: self.fit.or(fallback.fit),
+            focus: self.focus.or(fallback.focus),
+            mask: self.mask.or(fallback.mask),
+            radius: self.radius.or(fallback.radius),
+            filter: self.filter.or(fallback.filter),
+            overlay: self.overlay.or(fallback.overlay),
+        }
+    }
+}
+
+/// Parse a mask corner radius, which must be finite and non-negative
+pub fn parse_radius(value: &str) -> Result<f32, String> {
+    match value.trim().parse::<f32>() {
+        Ok(radius) if radius >= 0.0 && radius.is_finite() => Ok(radius),
+        _ => Err(format!("Invalid mask radius: {}", value)),
+    }
+}
+
+/// An image to place in a template slot, with its requested presentation
+pub struct SlotImage {
+    pub image: ValidatedImage,
+    pub style: ImageStyle,
+}
@@ -1,8 +1,20 @@
 mod events;
+mod image_filter;
+mod image_overlay;
+mod image_style;
+mod partials;
 mod png;
 pub mod strategies;
 mod svg;
+mod template;
+mod template_params;
+mod text_metrics;
 mod utils;
 
-pub use png::render_to_png;
+pub use image_filter::ImageFilters;
+pub use image_overlay::ImageOverlay;
+pub use image_style::{FocalPoint, ImageFit, ImageStyle, MaskShape, SlotImage};
+pub use png::{OUTPUT_SCALE, render_to_png};
 pub use svg::generate_svg;
+pub use template::{Template, Templates};
+pub use text_metrics::Fonts;
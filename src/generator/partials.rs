@@ -0,0 +1,186 @@
+use quick_xml::Reader;
+use quick_xml::escape::escape;
+use quick_xml::events::{BytesStart, Event};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use super::utils::get_attr;
+
+/// Element replaced by the content of another file, e.g. `<ogis:include src="footer.svg"/>`
+const INCLUDE: &[u8] = b"ogis:include";
+
+/// Root element of a template that fills in the blocks of a base template
+const EXTENDS: &[u8] = b"ogis:extends";
+
+/// Named section of a base template that extending templates may replace
+const BLOCK: &[u8] = b"ogis:block";
+
+/// Read a template from the template directory, resolving includes and inheritance
+///
+/// `<ogis:include src="..."/>` is replaced by the content of the named file, which may be
+/// a fragment without a root element. A template whose root is
+/// `<ogis:extends src="base.svg">` is the base template with each
+/// `<ogis:block name="...">` replaced by the extending template's block of the same name;
+/// blocks it does not override keep the base's content, and anything outside its blocks
+/// is ignored. Bases may extend other bases. Paths are relative to the template directory.
+pub fn resolve(dir: &Path, file: &str) -> Result<String, String> {
+    let source = resolve_file(dir, file, &mut Vec::new())?;
+    unwrap_blocks(&source)
+}
+
+/// Resolve a file, keeping block elements so templates extending it can still find them
+///
+/// `stack` holds the files being resolved, to report cycles instead of recursing forever.
+fn resolve_file(dir: &Path, file: &str, stack: &mut Vec<String>) -> Result<String, String> {
+    if stack.iter().any(|f| f == file) {
+        return Err(format!(
+            "template includes itself: {} -> {}",
+            stack.join(" -> "),
+            file
+        ));
+    }
+
+    let path = template_path(dir, file)?;
+    let source = std::fs::read_to_string(&path)
+        .map_err(|e| format!("failed to read template {}: {}", path.display(), e))?;
+
+    stack.push(file.to_string());
+    let resolved = match parse_extends(&source) {
+        Ok(Some(Extends { base, blocks })) => {
+            resolve_file(dir, &base, stack).and_then(|base| fill_blocks(&base, &blocks))
+        }
+        Ok(None) => Ok(source),
+        Err(e) => Err(e),
+    }
+    .and_then(|source| {
+        rewrite(&source, INCLUDE, |e, _| {
+            resolve_file(dir, &get_attr(e, "src")?, stack)
+        })
+    });
+    stack.pop();
+
+    resolved.map_err(|e| format!("{} (in {})", e, file))
+}
+
+/// Path of a file in the template directory, which relative paths may not leave
+fn template_path(dir: &Path, file: &str) -> Result<PathBuf, String> {
+    let relative = Path::new(file);
+    let inside = relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !inside || file.is_empty() {
+        return Err(format!("invalid template path: {}", file));
+    }
+    Ok(dir.join(relative))
+}
+
+/// Base template and overridden blocks of an extending template
+struct Extends {
+    base: String,
+    /// Content of each overridden block, by name
+    blocks: HashMap<String, String>,
+}
+
+/// Find the base template and overridden blocks of an extending template
+///
+/// Returns None if the template's root element is not `<ogis:extends>`.
+fn parse_extends(source: &str) -> Result<Option<Extends>, String> {
+    let mut reader = Reader::from_str(source);
+    let (root, is_empty) = loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) => break (e, false),
+            Ok(Event::Empty(e)) => break (e, true),
+            Ok(Event::Eof) => return Ok(None),
+            Ok(_) => {}
+            Err(e) => return Err(format!("template parse error: {}", e)),
+        }
+    };
+    if root.name().as_ref() != EXTENDS {
+        return Ok(None);
+    }
+
+    let base = get_attr(&root, "src")?;
+    let mut blocks = HashMap::new();
+    if is_empty {
+        return Ok(Some(Extends { base, blocks }));
+    }
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) if e.name().as_ref() == BLOCK => {
+                let span = reader
+                    .read_to_end(e.name())
+                    .map_err(|e| format!("template parse error: {}", e))?;
+                let content = &source[span.start as usize..span.end as usize];
+                blocks.insert(get_attr(&e, "name")?, content.to_string());
+            }
+            Ok(Event::Empty(e)) if e.name().as_ref() == BLOCK => {
+                blocks.insert(get_attr(&e, "name")?, String::new());
+            }
+            Ok(Event::Eof) => break,
+            Ok(_) => {}
+            Err(e) => return Err(format!("template parse error: {}", e)),
+        }
+    }
+
+    Ok(Some(Extends { base, blocks }))
+}
+
+/// Replace the content of the base's blocks with the overriding blocks of the same name
+fn fill_blocks(base: &str, blocks: &HashMap<String, String>) -> Result<String, String> {
+    rewrite(base, BLOCK, |e, content| {
+        let name = get_attr(e, "name")?;
+        let content = match blocks.get(&name) {
+            Some(content) => content.clone(),
+            // Blocks nested in a block that is not overridden can still be overridden
+            None => fill_blocks(content, blocks)?,
+        };
+        Ok(format!(
+            "<ogis:block name=\"{}\">{}</ogis:block>",
+            escape(&name),
+            content
+        ))
+    })
+}
+
+/// Replace block elements by their content once inheritance is resolved
+fn unwrap_blocks(source: &str) -> Result<String, String> {
+    rewrite(source, BLOCK, |_, content| unwrap_blocks(content))
+}
+
+/// Replace every element named `name` by the result of `replace`, keeping the rest of the
+/// source byte for byte
+///
+/// `replace` gets the element and its content, which is empty for self-closing elements.
+fn rewrite(
+    source: &str,
+    name: &[u8],
+    mut replace: impl FnMut(&BytesStart, &str) -> Result<String, String>,
+) -> Result<String, String> {
+    let mut reader = Reader::from_str(source);
+    let mut result = String::with_capacity(source.len());
+    let mut copied = 0;
+
+    loop {
+        let start = reader.buffer_position() as usize;
+        let (e, content) = match reader.read_event() {
+            Ok(Event::Start(e)) if e.name().as_ref() == name => {
+                let span = reader
+                    .read_to_end(e.name())
+                    .map_err(|e| format!("template parse error: {}", e))?;
+                (e, &source[span.start as usize..span.end as usize])
+            }
+            Ok(Event::Empty(e)) if e.name().as_ref() == name => (e, ""),
+            Ok(Event::Eof) => break,
+            Ok(_) => continue,
+            Err(e) => return Err(format!("template parse error: {}", e)),
+        };
+
+        result.push_str(&source[copied..start]);
+        result.push_str(&replace(&e, content)?);
+        copied = reader.buffer_position() as usize;
+    }
+    result.push_str(&source[copied..]);
+
+    Ok(result)
+}
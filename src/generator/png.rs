@@ -1,24 +1,30 @@
 use std::sync::Arc;
 
-pub fn render_to_png(
-    svg_data: &str,
-    fontdb: &Arc<usvg::fontdb::Database>,
-) -> Result<Vec<u8>, String> {
+use super::text_metrics::Fonts;
+
+/// Output pixels per template unit
+pub const OUTPUT_SCALE: f32 = 1.0;
+
+pub fn render_to_png(svg_data: &str, fonts: &Fonts) -> Result<Vec<u8>, String> {
     let options = usvg::Options {
-        fontdb: Arc::clone(fontdb),
+        fontdb: Arc::clone(fonts.db()),
         ..Default::default()
     };
     let tree = usvg::Tree::from_str(svg_data, &options)
         .map_err(|e| format!("Failed to parse SVG: {}", e))?;
 
     let size = tree.size();
-    let width = size.width().round() as u32;
-    let height = size.height().round() as u32;
+    let width = (size.width() * OUTPUT_SCALE).round() as u32;
+    let height = (size.height() * OUTPUT_SCALE).round() as u32;
 
     let mut pixmap = tiny_skia::Pixmap::new(width, height)
         .ok_or_else(|| "Failed to create pixmap".to_string())?;
 
-    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(OUTPUT_SCALE, OUTPUT_SCALE),
+        &mut pixmap.as_mut(),
+    );
 
     pixmap
         .encode_png()
@@ -2,13 +2,20 @@ use quick_xml::Writer;
 use quick_xml::events::{BytesStart, Event};
 use std::io::Cursor;
 
+use crate::generator::image_style::{ImageFit, ImageStyle, MaskShape};
 use crate::generator::utils::{get_attr, write_event};
 
-/// Strategy for replacing a group element with an SVG <image> element with rounded corners
+/// Number of points used to approximate a squircle outline
+const SQUIRCLE_POINTS: usize = 72;
+
+/// Strategy for replacing a group element with an SVG <image> element clipped to a shape
 ///
 /// The positioning and sizing is determined by reading a <rect> child element.
 /// The rect's x, y, width, height, and rx attributes define the bounding box and corner radius.
-/// The image is centered and scaled to fit while maintaining aspect ratio.
+/// By default the image is centered and scaled to fit while maintaining aspect ratio, inside
+/// a rect with the slot's rounded corners; `style` selects other fit modes, focal points,
+/// mask shapes, filters and an overlay painted over the image in the same shape. `id` makes
+/// the IDs of these defs unique, as slots in different groups may share coordinates.
 ///
 /// Example input:
 /// <g id="ogis_logo">
@@ -19,71 +26,200 @@ use crate::generator::utils::{get_attr, write_event};
 /// Example output:
 /// <g>
 ///   <defs>
-///     <clipPath id="clip_80_60"><rect x="80" y="60" width="400" height="120" rx="12"/></clipPath>
+///     <clipPath id="clip_1"><rect x="80" y="60" width="400" height="120" rx="12"/></clipPath>
 ///   </defs>
-///   <image x="80" y="60" width="400" height="120" preserveAspectRatio="xMidYMid meet" clip-path="url(#clip_80_60)" href="data:image/png;base64,..."/>
+///   <image x="80" y="60" width="400" height="120" preserveAspectRatio="xMidYMid meet" clip-path="url(#clip_1)" href="data:image/png;base64,..."/>
 /// </g>
 pub fn replace(
     rect_element: &BytesStart,
     image_bytes: &[u8],
     mime_type: &str,
+    image_size: Option<(f32, f32)>,
+    style: &ImageStyle,
+    id: usize,
     writer: &mut Writer<Cursor<Vec<u8>>>,
 ) -> Result<(), String> {
     // Extract positioning attributes from the rect
-    let x = get_attr(rect_element, "x")?;
-    let y = get_attr(rect_element, "y")?;
-    let width = get_attr(rect_element, "width")?;
-    let height = get_attr(rect_element, "height")?;
+    let x = number_attr(rect_element, "x")?;
+    let y = number_attr(rect_element, "y")?;
+    let width = number_attr(rect_element, "width")?;
+    let height = number_attr(rect_element, "height")?;
 
-    // Extract rx (border radius) if present, default to "0" if not
-    let rx = get_attr(rect_element, "rx").unwrap_or_else(|_| "0".to_string());
+    // Corner radius from the style, else the rect's rx if present, else square
+    let rx = style
+        .radius
+        .or_else(|| number_attr(rect_element, "rx").ok())
+        .unwrap_or(0.0);
 
     // Convert image bytes to base64 at write time
     use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
     let image_base64 = BASE64.encode(image_bytes);
 
-    // Generate unique clip path ID based on position
-    let clip_id = format!("clip_{}_{}", x, y);
+    let clip_id = format!("clip_{}", id);
 
     // Start wrapper group
     write_event(writer, Event::Start(BytesStart::new("g")))?;
 
-    // Create <defs> with clip path for rounded corners
+    // Create <defs> with clip path for the mask shape
     write_event(writer, Event::Start(BytesStart::new("defs")))?;
 
     let mut clip_path = BytesStart::new("clipPath");
     clip_path.push_attribute(("id", clip_id.as_str()));
     write_event(writer, Event::Start(clip_path.clone()))?;
+    let mask = mask_element(style.mask.unwrap_or_default(), (x, y, width, height), rx);
+    write_event(writer, Event::Empty(mask.clone()))?;
+    write_event(writer, Event::End(clip_path.to_end()))?;
 
-    let mut clip_rect = BytesStart::new("rect");
-    clip_rect.push_attribute(("x", x.as_str()));
-    clip_rect.push_attribute(("y", y.as_str()));
-    clip_rect.push_attribute(("width", width.as_str()));
-    clip_rect.push_attribute(("height", height.as_str()));
-    clip_rect.push_attribute(("rx", rx.as_str()));
-    write_event(writer, Event::Empty(clip_rect))?;
+    // Filter chain for the image, if any step needs an SVG filter
+    let filter_id = format!("filter_{}", id);
+    let filter = style.filter.as_ref().filter(|f| f.needs_filter_element());
+    if let Some(filter) = filter {
+        filter.write_filter(&filter_id, writer)?;
+    }
 
-    write_event(writer, Event::End(clip_path.to_end()))?;
+    let overlay_id = format!("overlay_{}", id);
+    if let Some(overlay) = &style.overlay {
+        overlay.write_defs(&overlay_id, writer)?;
+    }
     write_event(writer, Event::End(BytesStart::new("defs").to_end()))?;
 
-    // Create <image> element with centered, aspect-ratio-preserving behavior
+    // Position the image according to the fit mode and focal point
+    let (bounds, preserve_aspect_ratio) = place_image(style, (x, y, width, height), image_size);
+    let (img_x, img_y, img_width, img_height) = bounds;
+
     let mut image_elem = BytesStart::new("image");
-    image_elem.push_attribute(("x", x.as_str()));
-    image_elem.push_attribute(("y", y.as_str()));
-    image_elem.push_attribute(("width", width.as_str()));
-    image_elem.push_attribute(("height", height.as_str()));
-    image_elem.push_attribute(("preserveAspectRatio", "xMidYMid meet"));
+    image_elem.push_attribute(("x", img_x.to_string().as_str()));
+    image_elem.push_attribute(("y", img_y.to_string().as_str()));
+    image_elem.push_attribute(("width", img_width.to_string().as_str()));
+    image_elem.push_attribute(("height", img_height.to_string().as_str()));
+    image_elem.push_attribute(("preserveAspectRatio", preserve_aspect_ratio));
 
     // Use href attribute with base64 data URI (with correct MIME type)
     let data_uri = format!("data:{};base64,{}", mime_type, image_base64);
     image_elem.push_attribute(("href", data_uri.as_str()));
 
-    // Apply clip path for rounded corners
+    // Apply clip path for the mask shape
     let clip_path_url = format!("url(#{})", clip_id);
     image_elem.push_attribute(("clip-path", clip_path_url.as_str()));
 
+    if filter.is_some() {
+        let filter_url = format!("url(#{})", filter_id);
+        image_elem.push_attribute(("filter", filter_url.as_str()));
+    }
+    if let Some(opacity) = style.filter.as_ref().and_then(|f| f.opacity()) {
+        image_elem.push_attribute(("opacity", opacity.to_string().as_str()));
+    }
+
     write_event(writer, Event::Empty(image_elem))?;
 
+    // Overlay over the image, in the shape of the mask
+    if let Some(overlay) = style
+        .overlay
+        .as_ref()
+        .and_then(|o| o.fill(mask, &overlay_id))
+    {
+        write_event(writer, Event::Empty(overlay))?;
+    }
+
     // Close wrapper group
     write_event(writer, Event::End(BytesStart::new("g").to_end()))
 }
+
+/// Compute the image's bounds and `preserveAspectRatio` for the fit mode
+///
+/// Contain and cover are laid out explicitly from the image's intrinsic size so the focal
+/// point can be honored. If the size is unknown, the renderer centers the image instead.
+fn place_image(
+    style: &ImageStyle,
+    slot: (f32, f32, f32, f32),
+    image_size: Option<(f32, f32)>,
+) -> ((f32, f32, f32, f32), &'static str) {
+    let fit = style.fit.unwrap_or_default();
+    let focus = style.focus.unwrap_or_default();
+
+    let scale_by = match fit {
+        ImageFit::Fill => return (slot, "none"),
+        ImageFit::Contain if style.focus.is_none() => return (slot, "xMidYMid meet"),
+        ImageFit::Contain => f32::min,
+        ImageFit::Cover => f32::max,
+    };
+
+    let Some((image_width, image_height)) = image_size else {
+        let fallback = match fit {
+            ImageFit::Cover => "xMidYMid slice",
+            _ => "xMidYMid meet",
+        };
+        return (slot, fallback);
+    };
+
+    let (x, y, width, height) = slot;
+    let scale = scale_by(width / image_width, height / image_height);
+    let (scaled_width, scaled_height) = (image_width * scale, image_height * scale);
+
+    let bounds = (
+        x + (width - scaled_width) * focus.x,
+        y + (height - scaled_height) * focus.y,
+        scaled_width,
+        scaled_height,
+    );
+    (bounds, "none")
+}
+
+/// Build the clip shape for a mask within the slot bounds
+fn mask_element(shape: MaskShape, slot: (f32, f32, f32, f32), rx: f32) -> BytesStart<'static> {
+    let (x, y, width, height) = slot;
+    let (cx, cy) = (x + width / 2.0, y + height / 2.0);
+
+    match shape {
+        MaskShape::Rect | MaskShape::Rounded => {
+            let rx = if shape == MaskShape::Rect { 0.0 } else { rx };
+            let mut rect = BytesStart::new("rect");
+            rect.push_attribute(("x", x.to_string().as_str()));
+            rect.push_attribute(("y", y.to_string().as_str()));
+            rect.push_attribute(("width", width.to_string().as_str()));
+            rect.push_attribute(("height", height.to_string().as_str()));
+            rect.push_attribute(("rx", rx.to_string().as_str()));
+            rect
+        }
+        MaskShape::Circle => {
+            let mut circle = BytesStart::new("circle");
+            circle.push_attribute(("cx", cx.to_string().as_str()));
+            circle.push_attribute(("cy", cy.to_string().as_str()));
+            circle.push_attribute(("r", (width.min(height) / 2.0).to_string().as_str()));
+            circle
+        }
+        MaskShape::Squircle => {
+            let mut path = BytesStart::new("path");
+            path.push_attribute((
+                "d",
+                squircle_path(cx, cy, width / 2.0, height / 2.0).as_str(),
+            ));
+            path
+        }
+    }
+}
+
+/// Outline of the superellipse |x/a|^4 + |y/b|^4 = 1 as a closed polygon path
+fn squircle_path(cx: f32, cy: f32, a: f32, b: f32) -> String {
+    let points: Vec<String> = (0..SQUIRCLE_POINTS)
+        .map(|i| {
+            let theta = std::f32::consts::TAU * i as f32 / SQUIRCLE_POINTS as f32;
+            let (sin, cos) = theta.sin_cos();
+            let px = cx + a * cos.signum() * cos.abs().sqrt();
+            let py = cy + b * sin.signum() * sin.abs().sqrt();
+            format!("{:.2},{:.2}", px, py)
+        })
+        .collect();
+
+    format!("M{}Z", points.join(" L"))
+}
+
+/// Read a numeric attribute, allowing a `px` suffix
+fn number_attr(e: &BytesStart, name: &str) -> Result<f32, String> {
+    let value = get_attr(e, name)?;
+    value
+        .trim()
+        .trim_end_matches("px")
+        .parse()
+        .map_err(|_| format!("Invalid number in {} attribute: {}", name, value))
+}
@@ -2,51 +2,73 @@ use quick_xml::events::Event;
 use quick_xml::{Reader, Writer};
 use std::collections::HashMap;
 use std::io::Cursor;
+use std::sync::Arc;
 
 use super::events::{
     ImageReplacement, State, handle_default, handle_empty, handle_end, handle_start,
 };
+use super::image_style::{ImageStyle, SlotImage};
+use super::template::Template;
+use super::text_metrics::Fonts;
 use crate::image::ValidatedImage;
 
-const DEFAULT_TEMPLATE: &str = include_str!("../../templates/twilight.svg");
-
+/// Render a template with the given content
+///
+/// `text` maps text slot names (`title`, `description`, `subtitle`) to their text, and
+/// `lists` maps list slot names to their items. `fonts` are used to measure list items
+/// and should be the ones the SVG is rendered with.
 pub fn generate_svg(
-    title: &str,
-    description: &str,
-    subtitle: &str,
-    logo: Option<ValidatedImage>,
-    image: Option<ValidatedImage>,
+    template: &Template,
+    text: HashMap<String, String>,
+    images: HashMap<String, SlotImage>,
+    lists: HashMap<String, Vec<String>>,
+    params: HashMap<String, String>,
+    assets: HashMap<String, ValidatedImage>,
+    fonts: &Arc<Fonts>,
 ) -> Result<String, String> {
-    let mut reader = Reader::from_str(DEFAULT_TEMPLATE);
+    let mut reader = Reader::from_str(template.source());
     reader.config_mut().trim_text(false);
 
     let mut writer = Writer::new(Cursor::new(Vec::new()));
 
-    // Create text replacement map: element ID -> replacement text
-    let text_replacements = HashMap::from([
-        ("ogis_title".to_string(), title.to_string()),
-        ("ogis_description".to_string(), description.to_string()),
-        ("ogis_subtitle".to_string(), subtitle.to_string()),
-    ]);
-
-    // Convert ValidatedImage to ImageReplacement
-    let logo_replacement = logo.map(|v| ImageReplacement {
-        bytes: v.bytes,
-        mime_type: v.mime_type,
-    });
-    let image_replacement = image.map(|v| ImageReplacement {
-        bytes: v.bytes,
-        mime_type: v.mime_type,
-    });
-
-    // Create image replacement map: element ID -> Option<ImageReplacement>
+    // Create image replacement map: slot name -> Option<ImageReplacement>
     // None means remove the element, Some means replace with image
-    let image_replacements = HashMap::from([
-        ("ogis_logo".to_string(), logo_replacement),
-        ("ogis_image".to_string(), image_replacement),
-    ]);
+    let mut image_replacements: HashMap<String, Option<ImageReplacement>> = template
+        .image_slots()
+        .keys()
+        .map(|name| (name.clone(), None))
+        .collect();
+    for (name, v) in images {
+        let replacement = ImageReplacement {
+            bytes: v.image.bytes,
+            mime_type: v.image.mime_type,
+            size: v.image.size,
+            style: v.style,
+        };
+        image_replacements.insert(name, Some(replacement));
+    }
+
+    let assets = assets
+        .into_iter()
+        .map(|(href, v)| {
+            let replacement = ImageReplacement {
+                bytes: v.bytes,
+                mime_type: v.mime_type,
+                size: v.size,
+                style: ImageStyle::default(),
+            };
+            (href, replacement)
+        })
+        .collect();
 
-    let mut state = State::new(text_replacements, image_replacements);
+    let mut state = State::new(
+        text,
+        image_replacements,
+        lists,
+        params,
+        assets,
+        Arc::clone(fonts),
+    );
     let mut buf = Vec::new();
 
     loop {
@@ -55,7 +77,7 @@ pub fn generate_svg(
             Ok(Event::Start(e)) => handle_start(e, &mut writer, &mut state)?,
             Ok(Event::Empty(e)) => handle_empty(e, &mut writer, &mut state)?,
             Ok(Event::End(e)) => handle_end(e, &mut writer, &mut state)?,
-            Ok(e) => handle_default(e, &mut writer, &state)?,
+            Ok(e) => handle_default(e, &mut writer, &mut state)?,
             Err(e) => return Err(format!("Parse error: {:?}", e)),
         }
         buf.clear();
@@ -63,3 +85,347 @@ pub fn generate_svg(
 
     String::from_utf8(writer.into_inner().into_inner()).map_err(|e| format!("UTF-8 error: {}", e))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::image_style::MaskShape;
+    use crate::generator::text_metrics::{FontSpec, text_width};
+
+    const SVG_START: &str =
+        r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:ogis="https://ogis.dev/template">"#;
+
+    /// Content to render a template with
+    #[derive(Default)]
+    struct Content<'a> {
+        text: &'a [(&'a str, &'a str)],
+        /// Slots given a 2x1 PNG, with the requested style
+        images: Vec<(&'a str, ImageStyle)>,
+        lists: &'a [(&'a str, &'a [&'a str])],
+        params: &'a [(&'a str, &'a str)],
+    }
+
+    fn render(body: &str, content: Content) -> String {
+        let template = Template::new(format!("{}{}</svg>", SVG_START, body)).unwrap();
+        let to_map = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+
+        let images = content
+            .images
+            .into_iter()
+            .map(|(name, style)| {
+                let image = ValidatedImage {
+                    bytes: b"png".to_vec(),
+                    mime_type: "image/png".to_string(),
+                    size: Some((2.0, 1.0)),
+                };
+                (name.to_string(), SlotImage { image, style })
+            })
+            .collect();
+        let lists = content
+            .lists
+            .iter()
+            .map(|(name, items)| {
+                let items = items.iter().map(|i| i.to_string()).collect();
+                (name.to_string(), items)
+            })
+            .collect();
+        let params = template
+            .params()
+            .resolve(&to_map(content.params), None)
+            .unwrap();
+
+        let mut fontdb = usvg::fontdb::Database::new();
+        fontdb.load_fonts_dir("fonts");
+        generate_svg(
+            &template,
+            to_map(content.text),
+            images,
+            lists,
+            params,
+            HashMap::new(),
+            &Arc::new(Fonts::new(fontdb)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn slots_at_the_same_position_get_their_own_defs() {
+        let svg = render(
+            r#"<g transform="translate(0, 0)"><g id="ogis_logo"><rect x="0" y="0" width="40" height="40"/></g></g>
+               <g transform="translate(100, 0)"><g id="ogis_image"><rect x="0" y="0" width="80" height="40"/></g></g>"#,
+            Content {
+                images: vec![
+                    (
+                        "logo",
+                        ImageStyle {
+                            mask: Some(MaskShape::Circle),
+                            ..Default::default()
+                        },
+                    ),
+                    ("image", ImageStyle::default()),
+                ],
+                ..Default::default()
+            },
+        );
+
+        assert!(svg.contains(r#"<clipPath id="clip_1"><circle"#), "{}", svg);
+        assert!(svg.contains(r#"<clipPath id="clip_2"><rect"#), "{}", svg);
+        for clip in ["clip_1", "clip_2"] {
+            let reference = format!(r#"clip-path="url(#{})""#, clip);
+            assert_eq!(svg.matches(&reference).count(), 1, "{}", svg);
+        }
+    }
+
+    #[test]
+    fn renders_fallback_content_for_empty_slots() {
+        const SLOT: &str = r##"<g id="ogis_logo"><rect x="10" y="10" width="40" height="40" stroke-dasharray="4"/><circle r="5"/><g data-ogis-fallback=""><rect width="40" height="40" fill="#111"/><text>AB</text></g></g>"##;
+
+        let empty = render(SLOT, Content::default());
+        assert!(
+            empty.contains(
+                r##"<g id="ogis_logo"><g data-ogis-fallback=""><rect width="40" height="40" fill="#111"/><text>AB</text></g></g>"##
+            ),
+            "{}",
+            empty
+        );
+
+        let filled = render(
+            SLOT,
+            Content {
+                images: vec![("logo", ImageStyle::default())],
+                ..Default::default()
+            },
+        );
+        assert!(
+            filled.contains(r#"<image x="10" y="10" width="40" height="40""#),
+            "{}",
+            filled
+        );
+        assert!(!filled.contains("AB"), "{}", filled);
+        assert!(!filled.contains("<circle"), "{}", filled);
+        assert!(!filled.contains("stroke-dasharray"), "{}", filled);
+    }
+
+    #[test]
+    fn fills_generic_image_slots() {
+        let svg = render(
+            r#"<g id="ogis_img_avatar"><rect x="10" y="20" width="30" height="30"/></g>
+               <g data-ogis-image="sponsor"><rect x="100" y="20" width="60" height="30"/></g>
+               <g data-ogis-slot="img_empty"><rect x="200" y="20" width="30" height="30"/></g>
+               <g id="ogis_other"><rect x="300" y="20" width="30" height="30"/></g>"#,
+            Content {
+                images: vec![
+                    ("img_avatar", ImageStyle::default()),
+                    ("sponsor", ImageStyle::default()),
+                ],
+                ..Default::default()
+            },
+        );
+
+        assert!(
+            svg.contains(r#"<image x="10" y="20" width="30" height="30""#),
+            "{}",
+            svg
+        );
+        assert!(
+            svg.contains(r#"<image x="100" y="20" width="60" height="30""#),
+            "{}",
+            svg
+        );
+        // Empty slots are dropped, and `ogis_*` groups that are not image slots are kept
+        assert!(
+            svg.contains(r#"<g data-ogis-slot="img_empty"></g>"#),
+            "{}",
+            svg
+        );
+        assert!(
+            svg.contains(r#"<g id="ogis_other"><rect x="300" y="20" width="30" height="30"/></g>"#),
+            "{}",
+            svg
+        );
+    }
+
+    #[test]
+    fn fills_every_element_bound_to_a_slot() {
+        let svg = render(
+            r##"<text data-ogis-slot="title" fill="#000" x="2" y="2">Default</text>
+                <text data-ogis-slot="title">Default</text>
+                <text id="ogis_title">Default</text>
+                <g data-ogis-slot="logo"><rect x="0" y="0" width="10" height="10"/></g>
+                <g data-ogis-slot="logo"><rect x="50" y="0" width="20" height="20"/></g>"##,
+            Content {
+                text: &[("title", "A & <B>")],
+                images: vec![("logo", ImageStyle::default())],
+                ..Default::default()
+            },
+        );
+
+        assert!(
+            svg.contains(
+                r##"<text data-ogis-slot="title" fill="#000" x="2" y="2">A &amp; &lt;B&gt;</text>"##
+            ),
+            "{}",
+            svg
+        );
+        assert!(
+            svg.contains(r#"<text data-ogis-slot="title">A &amp; &lt;B&gt;</text>"#),
+            "{}",
+            svg
+        );
+        assert!(
+            svg.contains(r#"<text id="ogis_title">A &amp; &lt;B&gt;</text>"#),
+            "{}",
+            svg
+        );
+        assert!(!svg.contains("Default"), "{}", svg);
+        assert!(
+            svg.contains(r#"<image x="0" y="0" width="10" height="10""#),
+            "{}",
+            svg
+        );
+        assert!(
+            svg.contains(r#"<image x="50" y="0" width="20" height="20""#),
+            "{}",
+            svg
+        );
+    }
+
+    #[test]
+    fn substitutes_template_params_into_attributes() {
+        let svg = render(
+            r##"<ogis:param name="accent" type="color" default="#667eea"/>
+                <ogis:param name="size" type="number" min="0" default="4"/>
+                <ogis:param name="mode" type="enum" values="light,dark" default="light"/>
+                <rect fill="{{accent}}" stroke-width="{{ size }}" stroke="black" data-ogis-attr-stroke="accent"/>
+                <text aria-label="Tom &amp; {{mode}} &lt;3" data-ogis-attr-class="mode">x</text>"##,
+            Content {
+                params: &[
+                    ("accent", "rgb(255, 0, 0)"),
+                    ("size", "2.5"),
+                    ("mode", "dark"),
+                ],
+                ..Default::default()
+            },
+        );
+
+        assert!(
+            svg.contains(r##"<rect fill="#ff0000" stroke-width="2.5" stroke="#ff0000"/>"##),
+            "{}",
+            svg
+        );
+        assert!(
+            svg.contains(r#"<text aria-label="Tom &amp; dark &lt;3" class="dark">x</text>"#),
+            "{}",
+            svg
+        );
+        assert!(!svg.contains("{{"), "{}", svg);
+        assert!(!svg.contains("data-ogis-attr"), "{}", svg);
+
+        let defaults = render(
+            r##"<ogis:param name="accent" type="color" default="#667eea"/>
+                <rect fill="{{accent}}cc"/>"##,
+            Content::default(),
+        );
+        assert!(
+            defaults.contains(r##"<rect fill="#667eeacc"/>"##),
+            "{}",
+            defaults
+        );
+    }
+
+    #[test]
+    fn renders_conditional_sections() {
+        const BODY: &str = r##"<ogis:param name="shadow" type="flag" default="false"/><ogis:param name="mode" type="enum" values="light,dark" default="light"/><g data-ogis-if="subtitle"><text id="ogis_subtitle">S</text></g><g data-ogis-unless="subtitle"><text>no subtitle</text></g><g data-ogis-unless="image"><text id="ogis_title">wide</text></g><g data-ogis-if="image"><g id="ogis_image"><rect x="0" y="0" width="10" height="10"/></g></g><rect data-ogis-if="shadow" width="1" height="1"/><rect data-ogis-if="mode=dark" width="2" height="2"/><g data-ogis-if="tags"><text>tags</text></g>"##;
+
+        // Blank text does not count as set
+        let svg = render(
+            BODY,
+            Content {
+                text: &[("title", "T"), ("subtitle", "  ")],
+                params: &[("shadow", "on"), ("mode", "dark")],
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            &svg[SVG_START.len()..],
+            r#"<g data-ogis-unless="subtitle"><text>no subtitle</text></g><g data-ogis-unless="image"><text id="ogis_title">T</text></g><rect data-ogis-if="shadow" width="1" height="1"/><rect data-ogis-if="mode=dark" width="2" height="2"/></svg>"#
+        );
+
+        let svg = render(
+            BODY,
+            Content {
+                text: &[("title", "T"), ("subtitle", "Sub")],
+                images: vec![("image", ImageStyle::default())],
+                lists: &[("tags", &["rust"])],
+                ..Default::default()
+            },
+        );
+        assert!(
+            svg.contains(r#"<g data-ogis-if="subtitle"><text id="ogis_subtitle">Sub</text></g>"#),
+            "{}",
+            svg
+        );
+        assert!(
+            svg.contains(r#"<g data-ogis-if="image"><g id="ogis_image"><g><defs>"#),
+            "{}",
+            svg
+        );
+        assert!(
+            svg.contains(r#"<g data-ogis-if="tags"><text>tags</text></g>"#),
+            "{}",
+            svg
+        );
+        for hidden in ["no subtitle", "ogis_title", "shadow", "mode=dark"] {
+            assert!(!svg.contains(hidden), "{}: {}", hidden, svg);
+        }
+    }
+
+    #[test]
+    fn lays_out_list_items_by_measured_width() {
+        let svg = render(
+            r#"<g data-ogis-list="tags" data-ogis-max="4" data-ogis-gap="8" data-ogis-padding="10" data-ogis-width="150" font-family="Roboto Medium" font-weight="500" font-size="20"><rect height="30" width="{{item_width}}"/><text id="ogis_item">x</text></g>"#,
+            Content {
+                lists: &[("tags", &["rust", "svg", "open graph", "og", "dropped"])],
+                ..Default::default()
+            },
+        );
+
+        let mut fontdb = usvg::fontdb::Database::new();
+        fontdb.load_fonts_dir("fonts");
+        let fonts = Fonts::new(fontdb);
+        let font = FontSpec {
+            family: "Roboto Medium".to_string(),
+            size: 20.0,
+            weight: 500,
+        };
+        let width = |text| text_width(&fonts, text, &font) + 20.0;
+        let item = |text, x: f32, y: f32| {
+            format!(
+                r#"<g transform="translate({}, {})"><rect height="30" width="{}"/><text id="ogis_item">{}</text></g>"#,
+                x,
+                y,
+                width(text),
+                text
+            )
+        };
+
+        // Rows are 30 high (from the item rect) and 8 apart; "open graph" does not fit
+        // after "rust" and "svg" within 150, and nor does "og" after it
+        assert!(width("rust") + 8.0 + width("svg") + 8.0 + width("open graph") > 150.0);
+        let expected = [
+            item("rust", 0.0, 0.0),
+            item("svg", width("rust") + 8.0, 0.0),
+            item("open graph", 0.0, 38.0),
+            item("og", 0.0, 76.0),
+        ]
+        .concat();
+        assert!(svg.contains(&expected), "{}\n{}", expected, svg);
+        assert!(!svg.contains("dropped"), "{}", svg);
+        assert!(width("open graph") > width("og"));
+    }
+}
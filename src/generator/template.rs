@@ -0,0 +1,409 @@
+use quick_xml::Reader;
+use quick_xml::events::Event;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use super::events::replacements;
+use super::image_style;
+use super::partials;
+use super::template_params::{self, TemplateParams};
+use super::utils::get_attr;
+
+/// Template shipped with the binary, available without a template directory
+const BUILTIN_TEMPLATES: [(&str, &str); 1] =
+    [("twilight", include_str!("../../templates/twilight.svg"))];
+
+/// A template, resolved and scanned once when it is loaded
+pub struct Template {
+    source: String,
+    image_slots: HashMap<String, Option<(f32, f32)>>,
+    list_slots: HashSet<String>,
+    params: TemplateParams,
+    assets: HashMap<String, Option<(f32, f32)>>,
+}
+
+impl Template {
+    /// Scan a resolved template for its slots, parameters and assets
+    ///
+    /// Fails if the template is not well-formed, declares an invalid parameter or theme, refers
+    /// to a parameter it does not declare or sets an invalid `data-ogis-radius`, so broken templates are caught at startup
+    /// rather than on every request.
+    pub fn new(source: String) -> Result<Self, String> {
+        let params = scan_template_params(&source)?;
+        check_param_references(&source, &params)?;
+        check_image_radii(&source)?;
+
+        Ok(Self {
+            image_slots: scan_image_slots(&source)?,
+            list_slots: scan_list_slots(&source)?,
+            params,
+            assets: scan_template_assets(&source)?,
+            source,
+        })
+    }
+
+    /// SVG source, with includes and inheritance resolved
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Image slots declared by the template, by name, with the size of their bounding rect
+    ///
+    /// The size is used to downscale fetched images before they are embedded.
+    pub fn image_slots(&self) -> &HashMap<String, Option<(f32, f32)>> {
+        &self.image_slots
+    }
+
+    /// List slots declared by the template with `data-ogis-list`, by name
+    pub fn list_slots(&self) -> &HashSet<String> {
+        &self.list_slots
+    }
+
+    /// Parameters and themes declared by the template with `<ogis:param>` and `<ogis:theme>`
+    pub fn params(&self) -> &TemplateParams {
+        &self.params
+    }
+
+    /// `asset:` hrefs referenced by the template, with the size of the referencing element if set
+    pub fn assets(&self) -> &HashMap<String, Option<(f32, f32)>> {
+        &self.assets
+    }
+}
+
+/// Templates available to requests, by name
+pub struct Templates {
+    templates: HashMap<String, Template>,
+    default: String,
+}
+
+impl Templates {
+    /// Load the built-in templates and every `.svg` file directly inside `dir`
+    ///
+    /// Templates are named after their file without the extension and replace built-in
+    /// templates of the same name. Files starting with `_` are only used as partials and
+    /// bases. Templates that fail to resolve or are invalid are left out, unless it is the
+    /// default.
+    pub fn load(dir: Option<&Path>, default: &str) -> Result<Self, String> {
+        let mut templates = HashMap::new();
+        for (name, source) in BUILTIN_TEMPLATES {
+            let template = Template::new(source.to_string())
+                .map_err(|e| format!("invalid built-in template {}: {}", name, e))?;
+            templates.insert(name.to_string(), template);
+        }
+
+        if let Some(dir) = dir {
+            let entries = std::fs::read_dir(dir).map_err(|e| {
+                format!("failed to read template directory {}: {}", dir.display(), e)
+            })?;
+
+            for path in entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+            {
+                let Some(file) = path.file_name().and_then(|f| f.to_str()) else {
+                    continue;
+                };
+                let Some(name) = file.strip_suffix(".svg") else {
+                    continue;
+                };
+                if name.starts_with('_') || !path.is_file() {
+                    continue;
+                }
+
+                match partials::resolve(dir, file).and_then(Template::new) {
+                    Ok(template) => {
+                        templates.insert(name.to_string(), template);
+                    }
+                    Err(e) if name == default => {
+                        return Err(format!("failed to load default template {}: {}", name, e));
+                    }
+                    Err(e) => tracing::error!("Skipping template {}: {}", name, e),
+                }
+            }
+        }
+
+        if !templates.contains_key(default) {
+            return Err(format!("default template not found: {}", default));
+        }
+        tracing::info!("Loaded {} templates", templates.len());
+
+        Ok(Self {
+            templates,
+            default: default.to_string(),
+        })
+    }
+
+    /// Look up a template by name, or the default one
+    pub fn get(&self, name: Option<&str>) -> Result<&Template, String> {
+        let name = name.unwrap_or(&self.default);
+        self.templates.get(name).ok_or_else(|| {
+            let mut names: Vec<_> = self.templates.keys().map(String::as_str).collect();
+            names.sort_unstable();
+            format!(
+                "unknown template (expected one of {}): {}",
+                names.join(", "),
+                name
+            )
+        })
+    }
+}
+
+fn scan_template_params(template: &str) -> Result<TemplateParams, String> {
+    let mut reader = Reader::from_str(template);
+    let mut params = TemplateParams::default();
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e) | Event::Empty(e)) if template_params::is_declaration(&e) => {
+                params.declare(&e)?;
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(parse_error(&reader, e)),
+            _ => {}
+        }
+    }
+
+    Ok(params)
+}
+
+/// Check that every `{{name}}` placeholder and `data-ogis-attr-*` binding names a declared
+/// parameter
+///
+/// `item_width` is also available inside the item template of list slots.
+fn check_param_references(template: &str, params: &TemplateParams) -> Result<(), String> {
+    let mut reader = Reader::from_str(template);
+    // Whether each open element is inside a list slot's item template
+    let mut in_list = Vec::new();
+
+    loop {
+        let (e, is_start) = match reader.read_event() {
+            Ok(Event::Start(e)) => (e, true),
+            Ok(Event::Empty(e)) => (e, false),
+            Ok(Event::End(_)) => {
+                in_list.pop();
+                continue;
+            }
+            Ok(Event::Eof) if !in_list.is_empty() => {
+                return Err("template parse error: unclosed element at end of file".to_string());
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(parse_error(&reader, e)),
+            _ => continue,
+        };
+
+        let item = in_list.last().copied().unwrap_or(false);
+        for name in replacements::attribute::referenced_params(&e)? {
+            let declared = params.params.contains_key(&name)
+                || (item && name == replacements::list::ITEM_WIDTH_PARAM);
+            if !declared {
+                return Err(format!(
+                    "<{}> refers to undeclared template parameter: {}",
+                    String::from_utf8_lossy(e.name().as_ref()),
+                    name
+                ));
+            }
+        }
+
+        if is_start {
+            in_list.push(item || replacements::list::list_slot_name(&e).is_some());
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that every literal `data-ogis-radius` is a valid mask radius
+///
+/// Values filled from a parameter are only known at render time.
+fn check_image_radii(template: &str) -> Result<(), String> {
+    let mut reader = Reader::from_str(template);
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e) | Event::Empty(e)) => {
+                if let Ok(radius) = get_attr(&e, "data-ogis-radius")
+                    && !radius.contains("{{")
+                {
+                    image_style::parse_radius(&radius)?;
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(parse_error(&reader, e)),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn scan_list_slots(template: &str) -> Result<HashSet<String>, String> {
+    let mut reader = Reader::from_str(template);
+    let mut slots = HashSet::new();
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e) | Event::Empty(e)) => {
+                slots.extend(replacements::list::list_slot_name(&e));
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(parse_error(&reader, e)),
+            _ => {}
+        }
+    }
+
+    Ok(slots)
+}
+
+fn scan_template_assets(template: &str) -> Result<HashMap<String, Option<(f32, f32)>>, String> {
+    let mut reader = Reader::from_str(template);
+    let mut assets = HashMap::new();
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e) | Event::Empty(e)) => {
+                let href = e
+                    .attributes()
+                    .filter_map(|a| a.ok())
+                    .find(|attr| attr.key.local_name().as_ref() == b"href")
+                    .map(|attr| String::from_utf8_lossy(&attr.value).into_owned())
+                    .filter(|href| href.starts_with("asset:"));
+
+                if let Some(href) = href {
+                    let dimension = |name| get_attr(&e, name).ok()?.parse::<f32>().ok();
+                    let size = dimension("width").zip(dimension("height"));
+                    assets.insert(href, size);
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(parse_error(&reader, e)),
+            _ => {}
+        }
+    }
+
+    Ok(assets)
+}
+
+fn scan_image_slots(template: &str) -> Result<HashMap<String, Option<(f32, f32)>>, String> {
+    let mut reader = Reader::from_str(template);
+    let mut slots: HashMap<String, Option<(f32, f32)>> = HashMap::new();
+    let mut current_slot: Option<String> = None;
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) if current_slot.is_none() => {
+                current_slot = replacements::image::image_slot_name(&e);
+                if let Some(name) = &current_slot {
+                    slots.entry(name.clone()).or_insert(None);
+                }
+            }
+            Ok(Event::Start(e))
+                if current_slot.is_some() && replacements::image::is_fallback(&e) =>
+            {
+                // Fallback content is not part of the slot's bounds
+                reader
+                    .read_to_end(e.name())
+                    .map_err(|err| parse_error(&reader, err))?;
+            }
+            Ok(Event::Start(e) | Event::Empty(e))
+                if e.name().as_ref() == b"rect" && !replacements::image::is_fallback(&e) =>
+            {
+                // The first rect inside a slot group (outside fallback content) defines its bounds
+                // Groups sharing a slot get an image large enough for the biggest of them
+                if let Some(name) = current_slot.take() {
+                    let dimension = |name| get_attr(&e, name).ok()?.parse::<f32>().ok();
+                    if let (Some(width), Some(height)) = (dimension("width"), dimension("height")) {
+                        let size = slots.entry(name).or_insert(None);
+                        *size = Some(match *size {
+                            Some((w, h)) => (w.max(width), h.max(height)),
+                            None => (width, height),
+                        });
+                    }
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(parse_error(&reader, e)),
+            _ => {}
+        }
+    }
+
+    Ok(slots)
+}
+
+fn parse_error(reader: &Reader<&[u8]>, e: quick_xml::Error) -> String {
+    format!(
+        "template parse error at byte {}: {}",
+        reader.error_position(),
+        e
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(body: &str) -> Result<Template, String> {
+        Template::new(format!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" xmlns:ogis="https://ogis.dev/template"><ogis:param name="accent" type="color" default="#667eea"/>{}</svg>"##,
+            body
+        ))
+    }
+
+    #[test]
+    fn loads_builtin_templates() {
+        assert!(Templates::load(None, "twilight").is_ok());
+    }
+
+    #[test]
+    fn checks_param_references_at_load() {
+        assert!(template(r#"<rect fill="{{accent}}" data-ogis-attr-stroke="accent"/>"#).is_ok());
+        assert!(template(r#"<rect fill="{{accnt}}"/>"#).is_err());
+        assert!(template(r#"<rect data-ogis-attr-fill="accnt"/>"#).is_err());
+        assert!(template(r#"<rect fill="{{accent"/>"#).is_err());
+    }
+
+    #[test]
+    fn allows_item_width_in_list_items_only() {
+        assert!(template(r#"<g data-ogis-list="tags"><rect width="{{item_width}}"/></g>"#).is_ok());
+        assert!(template(r#"<rect width="{{item_width}}"/>"#).is_err());
+    }
+
+    #[test]
+    fn discovers_generic_image_slots() {
+        let template = template(
+            r#"<g id="ogis_img_avatar"><rect width="30" height="20"/></g>
+               <g data-ogis-image="sponsor"><rect width="60" height="30"/></g>
+               <g data-ogis-slot="img_avatar"><rect width="40" height="10"/></g>
+               <g id="ogis_other"><rect width="10" height="10"/></g>"#,
+        )
+        .unwrap();
+
+        let mut slots: Vec<_> = template.image_slots().iter().collect();
+        slots.sort_by(|a, b| a.0.cmp(b.0));
+        assert_eq!(
+            slots,
+            [
+                (&"img_avatar".to_string(), &Some((40.0, 20.0))),
+                (&"sponsor".to_string(), &Some((60.0, 30.0))),
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_image_radius_at_load() {
+        assert!(template(r#"<g id="ogis_logo"><rect data-ogis-radius="8"/></g>"#).is_ok());
+        for radius in ["-1", "NaN", "inf", "big"] {
+            let body = format!(
+                r#"<g id="ogis_logo"><rect data-ogis-radius="{}"/></g>"#,
+                radius
+            );
+            assert!(template(&body).is_err(), "{}", radius);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_templates() {
+        assert!(template("<g><rect/>").is_err());
+        assert!(template("<g></rect>").is_err());
+        assert!(template(r#"<ogis:param name="size" type="number" default="big"/>"#).is_err());
+    }
+}
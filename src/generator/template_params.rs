@@ -0,0 +1,235 @@
+use quick_xml::events::BytesStart;
+use std::collections::HashMap;
+use std::str::FromStr;
+use svgtypes::Color;
+
+use super::utils::get_attr;
+
+/// Element name of template parameter declarations
+const DECLARATION: &[u8] = b"ogis:param";
+
+/// Element name of theme declarations, which override parameter defaults
+const THEME_DECLARATION: &[u8] = b"ogis:theme";
+
+/// Kind of value a template parameter accepts
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParamKind {
+    /// A CSS color, written out as `#rrggbb` or `rgba(...)`
+    ///
+    /// Opaque colors reject transparency and are always written as `#rrggbb`, so templates
+    /// can append a hex alpha, e.g. `{{bg}}66`.
+    Color { opaque: bool },
+    /// A number, optionally bounded
+    Number { min: Option<f32>, max: Option<f32> },
+    /// One of a fixed set of keywords
+    Enum(Vec<String>),
+    /// An on/off switch, written out as `true` or `false`
+    Flag,
+}
+
+/// A parameter declared by the template with `<ogis:param>`
+///
+/// Example: `<ogis:param name="accent" type="color" default="#667eea"/>`. Colors accept
+/// `opaque="true"`, numbers accept `min` and `max`, enumerations list their `values` separated by commas. Flags
+/// (`type="flag"`) accept `true`/`false`, `1`/`0`, `yes`/`no` and `on`/`off`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TemplateParam {
+    pub name: String,
+    pub kind: ParamKind,
+    /// Normalized value used when the request does not set the parameter
+    pub default: String,
+}
+
+impl TemplateParam {
+    /// Parse a declaration element
+    pub fn from_element(e: &BytesStart) -> Result<Self, String> {
+        let name = get_attr(e, "name")?;
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("invalid template parameter name: {}", name));
+        }
+
+        let number = |attr: &str| -> Result<Option<f32>, String> {
+            get_attr(e, attr)
+                .ok()
+                .map(|v| {
+                    v.trim()
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|v| v.is_finite())
+                        .ok_or_else(|| format!("invalid {} for parameter {}: {}", attr, name, v))
+                })
+                .transpose()
+        };
+
+        let kind = match get_attr(e, "type")?.as_str() {
+            "color" => ParamKind::Color {
+                opaque: get_attr(e, "opaque").is_ok_and(|v| v.trim() == "true"),
+            },
+            "flag" => ParamKind::Flag,
+            "number" => ParamKind::Number {
+                min: number("min")?,
+                max: number("max")?,
+            },
+            "enum" => ParamKind::Enum(
+                get_attr(e, "values")?
+                    .split(',')
+                    .map(|v| v.trim().to_string())
+                    .filter(|v| !v.is_empty())
+                    .collect(),
+            ),
+            other => return Err(format!("unknown type for parameter {}: {}", name, other)),
+        };
+
+        let mut param = Self {
+            name,
+            kind,
+            default: String::new(),
+        };
+        param.default = param.parse_value(&get_attr(e, "default")?)?;
+        Ok(param)
+    }
+
+    /// Validate a requested value, returning it in the form written into the SVG
+    ///
+    /// Only colors, numbers, flags and declared keywords can come out of this, so substituted
+    /// values cannot inject markup or other attributes.
+    pub fn parse_value(&self, value: &str) -> Result<String, String> {
+        let value = value.trim();
+        let invalid = |expected: &str| {
+            format!(
+                "invalid value for {} (expected {}): {}",
+                self.name, expected, value
+            )
+        };
+
+        match &self.kind {
+            ParamKind::Color { opaque } => {
+                let color = Color::from_str(value).map_err(|_| invalid("a color"))?;
+                if *opaque && color.alpha != 255 {
+                    return Err(invalid("an opaque color"));
+                }
+                Ok(if color.alpha == 255 {
+                    format!("#{:02x}{:02x}{:02x}", color.red, color.green, color.blue)
+                } else {
+                    format!(
+                        "rgba({},{},{},{})",
+                        color.red,
+                        color.green,
+                        color.blue,
+                        color.alpha as f32 / 255.0
+                    )
+                })
+            }
+            ParamKind::Number { min, max } => {
+                let number = value
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|v| v.is_finite())
+                    .ok_or_else(|| invalid("a number"))?;
+                if min.is_some_and(|min| number < min) || max.is_some_and(|max| number > max) {
+                    let range = format!(
+                        "a number between {} and {}",
+                        min.map_or("-inf".to_string(), |v| v.to_string()),
+                        max.map_or("inf".to_string(), |v| v.to_string())
+                    );
+                    return Err(invalid(&range));
+                }
+                Ok(number.to_string())
+            }
+            ParamKind::Enum(values) => values
+                .iter()
+                .find(|v| v.as_str() == value)
+                .cloned()
+                .ok_or_else(|| invalid(&format!("one of {}", values.join(", ")))),
+            ParamKind::Flag => match value.to_ascii_lowercase().as_str() {
+                "true" | "1" | "yes" | "on" => Ok("true".to_string()),
+                "false" | "0" | "no" | "off" => Ok("false".to_string()),
+                _ => Err(invalid("true or false")),
+            },
+        }
+    }
+}
+
+/// Check if an element declares a template parameter or theme
+pub fn is_declaration(e: &BytesStart) -> bool {
+    matches!(e.name().as_ref(), DECLARATION | THEME_DECLARATION)
+}
+
+/// Parameters and themes declared by a template
+///
+/// A theme is declared as `<ogis:theme name="light" bg="#ffffff" fg="#111827"/>`, each
+/// other attribute overriding the default of the parameter it names.
+#[derive(Clone, Debug, Default)]
+pub struct TemplateParams {
+    pub params: HashMap<String, TemplateParam>,
+    pub themes: HashMap<String, HashMap<String, String>>,
+}
+
+impl TemplateParams {
+    /// Add a parameter or theme from a declaration element
+    ///
+    /// Themes may only refer to parameters declared before them.
+    pub fn declare(&mut self, e: &BytesStart) -> Result<(), String> {
+        if e.name().as_ref() == DECLARATION {
+            let param = TemplateParam::from_element(e)?;
+            self.params.insert(param.name.clone(), param);
+            return Ok(());
+        }
+
+        let name = get_attr(e, "name")?;
+        let mut values = HashMap::new();
+        for attr in e.attributes().filter_map(|a| a.ok()) {
+            let key = String::from_utf8_lossy(attr.key.as_ref()).into_owned();
+            if key == "name" {
+                continue;
+            }
+            let param = self
+                .params
+                .get(&key)
+                .ok_or_else(|| format!("theme {} sets unknown parameter {}", name, key))?;
+            values.insert(
+                key,
+                param.parse_value(&String::from_utf8_lossy(&attr.value))?,
+            );
+        }
+        self.themes.insert(name, values);
+        Ok(())
+    }
+
+    /// Validate requested values and fill in every declared parameter
+    ///
+    /// Requested values win over the theme's, which win over the declared defaults.
+    /// Request values that do not name a declared parameter are ignored.
+    pub fn resolve(
+        &self,
+        values: &HashMap<String, String>,
+        theme: Option<&str>,
+    ) -> Result<HashMap<String, String>, String> {
+        let theme = match theme {
+            Some(name) => Some(self.themes.get(name).ok_or_else(|| {
+                let mut names: Vec<_> = self.themes.keys().map(String::as_str).collect();
+                names.sort_unstable();
+                format!(
+                    "unknown theme (expected one of {}): {}",
+                    names.join(", "),
+                    name
+                )
+            })?),
+            None => None,
+        };
+
+        self.params
+            .iter()
+            .map(|(name, param)| {
+                let value = match values.get(name) {
+                    Some(value) => param.parse_value(value)?,
+                    None => theme
+                        .and_then(|theme| theme.get(name))
+                        .unwrap_or(&param.default)
+                        .clone(),
+                };
+                Ok((name.clone(), value))
+            })
+            .collect()
+    }
+}
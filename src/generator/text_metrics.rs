@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use usvg::fontdb::{Database, Family, ID, Query, Stretch, Style, Weight};
+
+/// Font size used by SVG renderers when a text element does not set one
+pub const DEFAULT_FONT_SIZE: f32 = 12.0;
+
+/// Fonts the SVG is rendered with, and the fallback face found so far for each character
+///
+/// Looking up a fallback face means trying every loaded face, so each character is only
+/// looked up once for the lifetime of the fonts.
+pub struct Fonts {
+    db: Arc<Database>,
+    fallbacks: Mutex<HashMap<char, Option<ID>>>,
+}
+
+impl Fonts {
+    pub fn new(db: Database) -> Self {
+        Self {
+            db: Arc::new(db),
+            fallbacks: Mutex::default(),
+        }
+    }
+
+    pub fn db(&self) -> &Arc<Database> {
+        &self.db
+    }
+
+    /// First loaded face that has a glyph for `c`, like the renderer's fallback
+    fn fallback_face(&self, c: char) -> Option<ID> {
+        if let Some(face) = self.fallbacks.lock().unwrap().get(&c) {
+            return *face;
+        }
+
+        let text = c.to_string();
+        let face =
+            self.db.faces().map(|face| face.id).find(|id| {
+                shape(&self.db, *id, &text).is_some_and(|(_, missing)| missing.is_empty())
+            });
+        self.fallbacks.lock().unwrap().insert(c, face);
+        face
+    }
+}
+
+/// Font properties of a text element, as written in its attributes
+#[derive(Clone, Debug, PartialEq)]
+pub struct FontSpec {
+    /// `font-family` list, e.g. `Roboto, sans-serif`
+    pub family: String,
+    pub size: f32,
+    /// Numeric `font-weight`, 400 being normal and 700 bold
+    pub weight: u16,
+}
+
+impl Default for FontSpec {
+    fn default() -> Self {
+        Self {
+            family: "sans-serif".to_string(),
+            size: DEFAULT_FONT_SIZE,
+            weight: 400,
+        }
+    }
+}
+
+impl FontSpec {
+    /// Set a property from a `font-family`, `font-size` or `font-weight` attribute
+    ///
+    /// Other attributes and values that cannot be parsed are ignored.
+    pub fn set(&mut self, attr: &str, value: &str) {
+        let value = value.trim();
+        match attr {
+            "font-family" if !value.is_empty() => self.family = value.to_string(),
+            "font-size" => {
+                if let Some(size) = value
+                    .trim_end_matches("px")
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|s| s.is_finite() && *s > 0.0)
+                {
+                    self.size = size;
+                }
+            }
+            "font-weight" => {
+                self.weight = match value {
+                    "normal" => 400,
+                    "bold" => 700,
+                    other => other.parse().unwrap_or(self.weight),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Measure the advance width of `text` in template units, as the renderer would lay it out
+///
+/// Text is shaped with the font the renderer picks for the spec. Characters it lacks are
+/// measured with the first loaded font that has them, like the renderer's fallback.
+/// Returns 0 if no font matches.
+pub fn text_width(fonts: &Fonts, text: &str, font: &FontSpec) -> f32 {
+    let families: Vec<Family> = font
+        .family
+        .split(',')
+        .map(|name| match name.trim().trim_matches(['"', '\'']) {
+            "serif" => Family::Serif,
+            "sans-serif" => Family::SansSerif,
+            "cursive" => Family::Cursive,
+            "fantasy" => Family::Fantasy,
+            "monospace" => Family::Monospace,
+            name => Family::Name(name),
+        })
+        .chain([Family::SansSerif])
+        .collect();
+    let query = Query {
+        families: &families,
+        weight: Weight(font.weight),
+        stretch: Stretch::Normal,
+        style: Style::Normal,
+    };
+    let Some(id) = fonts.db.query(&query) else {
+        return 0.0;
+    };
+
+    let (width, missing) = shape(&fonts.db, id, text).unwrap_or_default();
+    if missing.is_empty() {
+        return width * font.size;
+    }
+
+    // Shape the missing characters of each fallback face together, so joining scripts get
+    // their contextual forms
+    let mut by_face: Vec<(ID, String)> = Vec::new();
+    for c in missing {
+        let Some(face) = fonts.fallback_face(c) else {
+            continue;
+        };
+        match by_face.iter_mut().find(|(id, _)| *id == face) {
+            Some((_, text)) => text.push(c),
+            None => by_face.push((face, c.to_string())),
+        }
+    }
+    let fallback: f32 = by_face
+        .iter()
+        .filter_map(|(face, text)| shape(&fonts.db, *face, text))
+        .map(|(width, _)| width)
+        .sum();
+
+    (width + fallback) * font.size
+}
+
+/// Shape text with one font, returning its width in ems and the characters it has no glyph for
+fn shape(fontdb: &Database, id: ID, text: &str) -> Option<(f32, Vec<char>)> {
+    fontdb
+        .with_face_data(id, |data, index| {
+            let face = rustybuzz::Face::from_slice(data, index)?;
+            let mut buffer = rustybuzz::UnicodeBuffer::new();
+            buffer.push_str(text);
+            let glyphs = rustybuzz::shape(&face, &[], buffer);
+
+            let mut advance = 0;
+            let mut missing = Vec::new();
+            for (info, position) in glyphs.glyph_infos().iter().zip(glyphs.glyph_positions()) {
+                if info.glyph_id == 0 {
+                    missing.extend(text[info.cluster as usize..].chars().next());
+                } else {
+                    advance += position.x_advance;
+                }
+            }
+            Some((advance as f32 / face.units_per_em() as f32, missing))
+        })
+        .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fonts() -> Fonts {
+        let mut db = Database::new();
+        db.load_fonts_dir("fonts");
+        Fonts::new(db)
+    }
+
+    #[test]
+    fn measures_missing_characters_with_fallback_fonts() {
+        let fonts = fonts();
+        let font = FontSpec {
+            family: "Roboto Medium".to_string(),
+            weight: 500,
+            ..Default::default()
+        };
+
+        let latin = text_width(&fonts, "Hi", &font);
+        assert!(latin > 0.0);
+        assert!(fonts.fallbacks.lock().unwrap().is_empty());
+
+        // Arabic is not in Roboto, and is measured with the Arabic font
+        let mixed = text_width(&fonts, "Hi سلام", &font);
+        assert!(mixed > latin);
+        let arabic = fonts.fallback_face('س').unwrap();
+        let family = &fonts.db.face(arabic).unwrap().families[0].0;
+        assert!(family.contains("Arabic"), "{}", family);
+
+        // Fallback faces are looked up once per character
+        assert!(fonts.fallbacks.lock().unwrap().contains_key(&'س'));
+        assert_eq!(text_width(&fonts, "Hi سلام", &font), mixed);
+
+        // Characters no font has add nothing
+        assert_eq!(text_width(&fonts, "Hi\u{e000}", &font), latin);
+        assert_eq!(
+            fonts.fallbacks.lock().unwrap().get(&'\u{e000}'),
+            Some(&None)
+        );
+    }
+}
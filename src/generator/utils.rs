@@ -2,6 +2,12 @@ use quick_xml::Writer;
 use quick_xml::events::{BytesStart, Event};
 use std::io::Cursor;
 
+/// Attribute binding an element to a named slot, which several elements may share
+const SLOT_ATTR: &str = "data-ogis-slot";
+
+/// Prefix of element IDs that bind an element to the slot named by the rest of the ID
+const SLOT_ID_PREFIX: &str = "ogis_";
+
 /// Get an attribute value from an element as a String
 ///
 /// Returns an error if the attribute is missing or contains invalid UTF-8
@@ -22,6 +28,41 @@ pub fn get_id_from_element(element: &BytesStart) -> Option<String> {
     get_attr(element, "id").ok()
 }
 
+/// Get the name of the slot an element is bound to, if any
+///
+/// Uses `data-ogis-slot` when present, else an `ogis_*` ID without its prefix, so
+/// `data-ogis-slot="title"` and `id="ogis_title"` bind the same slot. Unlike IDs, the
+/// attribute may be repeated to show one value in several places.
+pub fn get_slot_from_element(element: &BytesStart) -> Option<String> {
+    if let Ok(slot) = get_attr(element, SLOT_ATTR) {
+        let slot = slot.trim();
+        return (!slot.is_empty()).then(|| slot.to_string());
+    }
+
+    get_id_from_element(element).and_then(|id| id.strip_prefix(SLOT_ID_PREFIX).map(str::to_string))
+}
+
+/// Split arguments on commas that are not nested inside parentheses, e.g. in `rgba(...)`
+pub fn split_top_level(args: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+
+    for (i, c) in args.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(args[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(args[start..].trim());
+
+    parts
+}
+
 /// Write an XML event to the writer with proper error handling
 ///
 /// Converts quick-xml write errors into descriptive error messages
@@ -0,0 +1,178 @@
+use moka::future::Cache;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use super::cache::CachedImage;
+use super::error::ImageFetchError;
+use super::fetch::FetchedImage;
+use super::freshness::CacheHeaders;
+use super::validate::{ImageLimits, validate_content_type};
+
+/// Local directory of images referenced as `asset:<path>`
+///
+/// Assets are read without any network access, validated like fetched images and kept in
+/// memory for the lifetime of the process.
+#[derive(Clone)]
+pub struct AssetStore {
+    root: Option<PathBuf>,
+    cache: Arc<Cache<String, CachedImage>>,
+}
+
+impl AssetStore {
+    pub fn new(root: Option<&Path>, cache_size: u64) -> std::io::Result<Self> {
+        // Canonicalize once so symlinks cannot be used to escape the directory
+        let root = root.map(|dir| dir.canonicalize()).transpose()?;
+        if let Some(dir) = &root {
+            tracing::info!("Serving image assets from {}", dir.display());
+        }
+
+        Ok(Self {
+            root,
+            cache: Arc::new(Cache::builder().max_capacity(cache_size).build()),
+        })
+    }
+
+    /// Stage 2 (assets): Load and validate an asset by its path relative to the assets directory
+    pub async fn load(
+        &self,
+        path: &str,
+        max_size: usize,
+        limits: &ImageLimits,
+    ) -> Result<CachedImage, ImageFetchError> {
+        if let Some(cached) = self.cache.get(path).await {
+            tracing::debug!("Asset cache hit: {}", path);
+            return Ok(cached);
+        }
+
+        let file = self.resolve(path)?;
+        let metadata = tokio::fs::metadata(&file)
+            .await
+            .map_err(|_| ImageFetchError::InvalidUrl(format!("Asset not found: {}", path)))?;
+        if metadata.len() as usize > max_size {
+            tracing::warn!("Asset {} exceeds max size: {}", path, metadata.len());
+            return Err(ImageFetchError::TooLarge);
+        }
+
+        let bytes = tokio::fs::read(&file).await.map_err(|e| {
+            ImageFetchError::Request(format!("Failed to read asset {}: {}", path, e))
+        })?;
+
+        let validated = validate_content_type(
+            FetchedImage {
+                bytes,
+                url: format!("asset:{}", path),
+                cache: CacheHeaders::default(),
+            },
+            limits,
+        )?;
+
+        let entry = CachedImage::new(validated, CacheHeaders::default(), Default::default());
+        self.cache.insert(path.to_string(), entry.clone()).await;
+        Ok(entry)
+    }
+
+    /// Map an asset path to a file inside the assets directory
+    fn resolve(&self, path: &str) -> Result<PathBuf, ImageFetchError> {
+        let root = self.root.as_ref().ok_or_else(|| {
+            ImageFetchError::InvalidUrl("asset: URLs require an assets directory".to_string())
+        })?;
+
+        let relative = Path::new(path);
+        let plain = relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)));
+        if path.is_empty() || !plain {
+            return Err(ImageFetchError::InvalidUrl(format!(
+                "Invalid asset path: {}",
+                path
+            )));
+        }
+
+        let file = root
+            .join(relative)
+            .canonicalize()
+            .map_err(|_| ImageFetchError::InvalidUrl(format!("Asset not found: {}", path)))?;
+        if !file.starts_with(root) {
+            tracing::warn!("Blocked asset path escaping the assets directory: {}", path);
+            return Err(ImageFetchError::InvalidUrl(format!(
+                "Invalid asset path: {}",
+                path
+            )));
+        }
+
+        Ok(file)
+    }
+}
+
+/// Split an `asset:` reference into its path, if it is one
+pub fn asset_path(url: &str) -> Option<&str> {
+    url.strip_prefix("asset:")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Assets directory with `logo.png`, next to an `outside` directory holding `secret.png`
+    fn store(name: &str) -> (AssetStore, PathBuf) {
+        let base =
+            std::env::temp_dir().join(format!("ogis-assets-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(base.join("assets/icons")).unwrap();
+        fs::create_dir_all(base.join("outside")).unwrap();
+        fs::write(base.join("assets/logo.png"), b"logo").unwrap();
+        fs::write(base.join("assets/icons/star.png"), b"star").unwrap();
+        fs::write(base.join("outside/secret.png"), b"secret").unwrap();
+
+        let store = AssetStore::new(Some(&base.join("assets")), 16).unwrap();
+        (store, base)
+    }
+
+    #[test]
+    fn resolves_paths_inside_the_root() {
+        let (store, base) = store("inside");
+        assert!(store.resolve("logo.png").is_ok());
+        assert!(store.resolve("icons/star.png").is_ok());
+        assert!(store.resolve("missing.png").is_err());
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn rejects_traversal() {
+        let (store, base) = store("traversal");
+        for path in [
+            "",
+            "../outside/secret.png",
+            "icons/../../outside/secret.png",
+            "icons/../logo.png",
+            "./logo.png",
+        ] {
+            assert!(store.resolve(path).is_err(), "{}", path);
+        }
+        let absolute = base.join("outside/secret.png");
+        assert!(store.resolve(absolute.to_str().unwrap()).is_err());
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_symlinks_escaping_the_root() {
+        use std::os::unix::fs::symlink;
+
+        let (store, base) = store("symlinks");
+        symlink(
+            base.join("outside/secret.png"),
+            base.join("assets/secret.png"),
+        )
+        .unwrap();
+        symlink(base.join("outside"), base.join("assets/linked")).unwrap();
+        symlink(base.join("assets/logo.png"), base.join("assets/alias.png")).unwrap();
+
+        assert!(store.resolve("secret.png").is_err());
+        assert!(store.resolve("linked/secret.png").is_err());
+        // Links that stay inside the root are fine
+        assert!(store.resolve("alias.png").is_ok());
+        fs::remove_dir_all(base).unwrap();
+    }
+}
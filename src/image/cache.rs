@@ -1,29 +1,195 @@
+use moka::Expiry;
 use moka::future::Cache;
-use std::sync::Arc;
-use std::time::Duration;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::error::{ImageFetchError, ImageFetchErrorKind};
+use super::freshness::CacheHeaders;
+use super::resize::TargetSize;
+use super::validate::ValidatedImage;
+
+/// Validated image bytes along with their HTTP freshness metadata
+#[derive(Clone)]
+pub struct CachedImage {
+    pub bytes: Arc<Vec<u8>>,
+    pub mime_type: String,
+    /// Intrinsic size probed during validation
+    pub size: Option<(f32, f32)>,
+    pub headers: CacheHeaders,
+    pub fresh_until: Instant,
+    /// Downscaled renditions of these bytes, by target size
+    pub variants: Arc<Mutex<HashMap<TargetSize, ValidatedImage>>>,
+}
+
+impl CachedImage {
+    pub fn new(image: ValidatedImage, headers: CacheHeaders, ttl: Duration) -> Self {
+        Self {
+            bytes: Arc::new(image.bytes),
+            mime_type: image.mime_type,
+            size: image.size,
+            headers,
+            fresh_until: Instant::now() + ttl,
+            variants: Arc::default(),
+        }
+    }
+
+    /// Same content with new freshness metadata, keeping existing renditions
+    pub fn refreshed(&self, headers: CacheHeaders, ttl: Duration) -> Self {
+        Self {
+            bytes: self.bytes.clone(),
+            mime_type: self.mime_type.clone(),
+            size: self.size,
+            headers,
+            fresh_until: Instant::now() + ttl,
+            variants: self.variants.clone(),
+        }
+    }
+
+    /// Whether the entry can be served without revalidation
+    pub fn is_fresh(&self) -> bool {
+        Instant::now() < self.fresh_until
+    }
+}
+
+/// Keeps each entry around for its freshness lifetime plus the stale window
+struct StaleWindowExpiry {
+    stale: Duration,
+}
+
+impl StaleWindowExpiry {
+    fn lifetime(&self, value: &CachedImage, now: Instant) -> Option<Duration> {
+        Some(value.fresh_until.saturating_duration_since(now) + self.stale)
+    }
+}
+
+impl Expiry<String, CachedImage> for StaleWindowExpiry {
+    fn expire_after_create(
+        &self,
+        _key: &String,
+        value: &CachedImage,
+        created_at: Instant,
+    ) -> Option<Duration> {
+        self.lifetime(value, created_at)
+    }
+
+    fn expire_after_update(
+        &self,
+        _key: &String,
+        value: &CachedImage,
+        updated_at: Instant,
+        _duration_until_expiry: Option<Duration>,
+    ) -> Option<Duration> {
+        self.lifetime(value, updated_at)
+    }
+}
 
 /// Cache wrapper for raw image bytes
+#[derive(Clone)]
 pub struct ImageCache {
-    cache: Arc<Cache<String, Arc<Vec<u8>>>>,
+    cache: Arc<Cache<String, CachedImage>>,
 }
 
 impl ImageCache {
-    pub fn new(cache_size: u64, cache_ttl_secs: u64) -> Self {
+    pub fn new(cache_size: u64, stale_secs: u64) -> Self {
+        let cache = Cache::builder()
+            .max_capacity(cache_size)
+            .expire_after(StaleWindowExpiry {
+                stale: Duration::from_secs(stale_secs),
+            })
+            .build();
+
+        Self {
+            cache: Arc::new(cache),
+        }
+    }
+
+    /// Look up an entry, which may be stale and in need of revalidation
+    pub async fn get(&self, url: &str) -> Option<CachedImage> {
+        self.cache.get(url).await
+    }
+
+    pub async fn insert(&self, url: String, entry: CachedImage) {
+        self.cache.insert(url, entry).await;
+    }
+
+    /// Drop an entry, e.g. once the origin forbids storing it
+    pub async fn invalidate(&self, url: &str) {
+        self.cache.invalidate(url).await;
+    }
+}
+
+/// TTLs for negative cache entries, per failure kind
+///
+/// A zero TTL disables negative caching for that kind.
+#[derive(Debug, Clone, Copy)]
+pub struct NegativeTtls {
+    pub request: Duration,
+    pub timeout: Duration,
+    pub http_status: Duration,
+    pub invalid: Duration,
+    pub blocked: Duration,
+}
+
+impl NegativeTtls {
+    fn ttl(&self, error: &ImageFetchError) -> Duration {
+        match error.kind() {
+            ImageFetchErrorKind::Request => self.request,
+            ImageFetchErrorKind::Timeout => self.timeout,
+            ImageFetchErrorKind::HttpStatus => self.http_status,
+            ImageFetchErrorKind::TooLarge
+            | ImageFetchErrorKind::TooComplex
+            | ImageFetchErrorKind::InvalidContentType => self.invalid,
+            ImageFetchErrorKind::PrivateIpBlocked | ImageFetchErrorKind::HostNotAllowed => {
+                self.blocked
+            }
+            // Parsing is cheap and deterministic, nothing to gain from caching it
+            ImageFetchErrorKind::InvalidUrl => Duration::ZERO,
+            // The circuit breaker already fails fast while the host is down
+            ImageFetchErrorKind::CircuitOpen => Duration::ZERO,
+        }
+    }
+}
+
+impl Expiry<String, ImageFetchError> for NegativeTtls {
+    fn expire_after_create(
+        &self,
+        _key: &String,
+        value: &ImageFetchError,
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        Some(self.ttl(value))
+    }
+}
+
+/// Cache of recent fetch failures so dead URLs fail fast
+#[derive(Clone)]
+pub struct FailureCache {
+    cache: Arc<Cache<String, ImageFetchError>>,
+    ttls: NegativeTtls,
+}
+
+impl FailureCache {
+    pub fn new(cache_size: u64, ttls: NegativeTtls) -> Self {
         let cache = Cache::builder()
             .max_capacity(cache_size)
-            .time_to_live(Duration::from_secs(cache_ttl_secs))
+            .expire_after(ttls)
             .build();
 
         Self {
             cache: Arc::new(cache),
+            ttls,
         }
     }
 
-    pub async fn get(&self, url: &str) -> Option<Vec<u8>> {
-        self.cache.get(url).await.map(|arc| (*arc).clone())
+    pub async fn get(&self, url: &str) -> Option<ImageFetchError> {
+        self.cache.get(url).await
     }
 
-    pub async fn insert(&self, url: String, bytes: Vec<u8>) {
-        self.cache.insert(url, Arc::new(bytes)).await;
+    pub async fn insert(&self, url: String, error: ImageFetchError) {
+        if self.ttls.ttl(&error).is_zero() {
+            return;
+        }
+        self.cache.insert(url, error).await;
     }
 }
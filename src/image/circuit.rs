@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::error::ImageFetchError;
+
+/// Hosts tracked at once; beyond this, hosts whose circuit is closed are forgotten
+const MAX_TRACKED_HOSTS: usize = 1024;
+
+#[derive(Default)]
+struct HostState {
+    /// Consecutive transient failures
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// Per-host circuit breaker that fails fast for hosts that keep failing
+///
+/// After `threshold` consecutive transient failures the circuit opens and fetches to the host
+/// are rejected for `open_for`. Then the circuit is half-open: a single fetch is let through
+/// as a trial while the others keep being rejected. Any answer from the host closes the
+/// circuit, another transient failure opens it again. If the trial never reports back, e.g.
+/// because its request was cancelled, the next trial is let through `open_for` later.
+/// A threshold of zero disables it.
+pub struct CircuitBreaker {
+    threshold: u32,
+    open_for: Duration,
+    hosts: Mutex<HashMap<String, HostState>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, open_for: Duration) -> Self {
+        Self {
+            threshold,
+            open_for,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Reject the fetch if the circuit for `host` is open, letting one trial through once
+    /// it is half-open
+    pub fn check(&self, host: &str) -> Result<(), ImageFetchError> {
+        let mut hosts = self.hosts.lock().unwrap();
+        let Some(until) = hosts
+            .get_mut(host)
+            .and_then(|state| state.open_until.as_mut())
+        else {
+            return Ok(());
+        };
+
+        let now = Instant::now();
+        if now < *until {
+            tracing::debug!("Circuit open for {}, skipping fetch", host);
+            return Err(ImageFetchError::CircuitOpen(host.to_string()));
+        }
+
+        // Keep rejecting other fetches while the trial is in flight
+        *until = now + self.open_for;
+        tracing::debug!(
+            "Circuit half-open for {}, letting a trial fetch through",
+            host
+        );
+        Ok(())
+    }
+
+    /// Update the circuit for `host` with the outcome of a fetch
+    pub fn record<T>(&self, host: &str, result: &Result<T, ImageFetchError>) {
+        if self.threshold == 0 {
+            return;
+        }
+
+        let mut hosts = self.hosts.lock().unwrap();
+        match result {
+            Ok(_) => {
+                if hosts
+                    .remove(host)
+                    .is_some_and(|state| state.open_until.is_some())
+                {
+                    tracing::info!("Circuit closed for {}", host);
+                }
+            }
+            Err(e) if e.is_transient() => {
+                if hosts.len() >= MAX_TRACKED_HOSTS && !hosts.contains_key(host) {
+                    hosts.retain(|_, state| state.open_until.is_some());
+                }
+
+                let state = hosts.entry(host.to_string()).or_default();
+                state.failures += 1;
+                if state.failures >= self.threshold {
+                    tracing::warn!(
+                        "Circuit opened for {} after {} consecutive failures",
+                        host,
+                        state.failures
+                    );
+                    state.open_until = Some(Instant::now() + self.open_for);
+                }
+            }
+            // The host answered; the request itself was the problem
+            Err(ImageFetchError::HttpStatus(_) | ImageFetchError::TooLarge) => {
+                if hosts
+                    .remove(host)
+                    .is_some_and(|state| state.open_until.is_some())
+                {
+                    tracing::info!("Circuit closed for {}", host);
+                }
+            }
+            // Failed before reaching the host, e.g. blocked by policy or a TLS error
+            Err(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_open_circuit_admits_one_trial() {
+        let circuit = CircuitBreaker::new(2, Duration::from_millis(20));
+        let failure: Result<(), _> = Err(ImageFetchError::Timeout);
+
+        circuit.record("example.com", &failure);
+        assert!(circuit.check("example.com").is_ok());
+        circuit.record("example.com", &failure);
+        assert!(circuit.check("example.com").is_err());
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(circuit.check("example.com").is_ok());
+        assert!(circuit.check("example.com").is_err());
+
+        circuit.record("example.com", &Ok::<_, ImageFetchError>(()));
+        assert!(circuit.check("example.com").is_ok());
+        assert!(circuit.check("example.com").is_ok());
+    }
+}
@@ -0,0 +1,172 @@
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use std::borrow::Cow;
+
+use super::error::ImageFetchError;
+use super::fetch::FetchedImage;
+use super::freshness::CacheHeaders;
+
+/// Stage 2 (data URIs): Decode a `data:image/...` URI with the same size limit as fetches
+///
+/// The payload may be base64 (`;base64,`) or percent-encoded, as in RFC 2397.
+pub fn decode_data_uri(uri: &str, max_size: usize) -> Result<FetchedImage, ImageFetchError> {
+    let rest = strip_prefix_ignore_case(uri, "data:")
+        .ok_or_else(|| ImageFetchError::InvalidUrl("Not a data URI".to_string()))?;
+
+    let (header, payload) = rest
+        .split_once(',')
+        .ok_or_else(|| ImageFetchError::InvalidUrl("Data URI has no payload".to_string()))?;
+
+    let mut parts = header.split(';');
+    let media_type = parts.next().unwrap_or_default().trim();
+    if !media_type.to_ascii_lowercase().starts_with("image/") {
+        return Err(ImageFetchError::InvalidUrl(format!(
+            "Data URI must have an image media type, got: {}",
+            media_type
+        )));
+    }
+    let is_base64 = parts.any(|p| p.trim().eq_ignore_ascii_case("base64"));
+
+    let payload = percent_decode(payload);
+    let bytes = if is_base64 {
+        // Line breaks are ignored, and unencoded '+' in query strings arrives as a space
+        let payload: Vec<u8> = payload
+            .into_iter()
+            .filter(|b| !matches!(b, b'\r' | b'\n' | b'\t'))
+            .map(|b| if b == b' ' { b'+' } else { b })
+            .collect();
+
+        // Check the decoded size before decoding anything
+        if payload.len() / 4 * 3 > max_size {
+            tracing::warn!(
+                "Data URI exceeds max size: ~{} > {}",
+                payload.len() / 4 * 3,
+                max_size
+            );
+            return Err(ImageFetchError::TooLarge);
+        }
+
+        BASE64.decode(payload).map_err(|e| {
+            ImageFetchError::InvalidUrl(format!("Invalid base64 in data URI: {}", e))
+        })?
+    } else {
+        payload
+    };
+    if bytes.len() > max_size {
+        tracing::warn!("Data URI exceeds max size: {} > {}", bytes.len(), max_size);
+        return Err(ImageFetchError::TooLarge);
+    }
+
+    Ok(FetchedImage {
+        bytes,
+        url: describe(uri).into_owned(),
+        cache: CacheHeaders::default(),
+    })
+}
+
+/// Whether an image source string is a data URI
+pub fn is_data_uri(uri: &str) -> bool {
+    strip_prefix_ignore_case(uri, "data:").is_some()
+}
+
+/// Short form of an image source for logs and error messages
+///
+/// Data URIs are reduced to their header and length.
+pub fn describe(uri: &str) -> Cow<'_, str> {
+    if !is_data_uri(uri) {
+        return Cow::Borrowed(uri);
+    }
+
+    let header = uri.split(',').next().unwrap_or(uri);
+    let header = header.get(..64).unwrap_or(header);
+    Cow::Owned(format!("{},... ({} chars)", header, uri.len()))
+}
+
+/// Decode `%XX` escapes, keeping invalid ones as-is
+fn percent_decode(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| s.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    decoded
+}
+
+fn strip_prefix_ignore_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    s.get(..prefix.len())
+        .filter(|head| head.eq_ignore_ascii_case(prefix))
+        .map(|_| &s[prefix.len()..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::ImageFetchErrorKind;
+
+    #[test]
+    fn decodes_base64() {
+        let image = decode_data_uri("data:image/png;base64,iVBORw0KGgo=", 1024).unwrap();
+        assert_eq!(image.bytes, b"\x89PNG\r\n\x1a\n");
+        assert_eq!(image.url, "data:image/png;base64,... (34 chars)");
+
+        // Line breaks, '+' turned into a space by query string decoding, and escapes
+        let image = decode_data_uri("DATA:image/png;BASE64, /8A\r\n/w%3D%3D", 1024).unwrap();
+        assert_eq!(image.bytes, [0xfb, 0xff, 0x00, 0xff]);
+    }
+
+    #[test]
+    fn decodes_percent_encoded() {
+        let uri = "data:image/svg+xml,%3Csvg%20xmlns='http://www.w3.org/2000/svg'/%3E";
+        let image = decode_data_uri(uri, 1024).unwrap();
+        assert_eq!(image.bytes, b"<svg xmlns='http://www.w3.org/2000/svg'/>");
+
+        let image = decode_data_uri("data:image/svg+xml;utf8,100%25 %zz", 1024).unwrap();
+        assert_eq!(image.bytes, b"100% %zz");
+    }
+
+    #[test]
+    fn rejects_oversize_payloads() {
+        let payload = "AAAA".repeat(10);
+        let uri = format!("data:image/png;base64,{}", payload);
+        assert!(decode_data_uri(&uri, 30).is_ok());
+        let err = decode_data_uri(&uri, 29).err().unwrap();
+        assert_eq!(err.kind(), ImageFetchErrorKind::TooLarge);
+
+        // Whitespace does not count towards the size
+        let uri = format!("data:image/png;base64,{}", payload.replace('A', "A\r\n"));
+        assert!(decode_data_uri(&uri, 30).is_ok());
+
+        let err = decode_data_uri("data:image/svg+xml,<svg/>", 5)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ImageFetchErrorKind::TooLarge);
+    }
+
+    #[test]
+    fn rejects_non_image_media_types() {
+        for uri in [
+            "data:text/html;base64,PHNjcmlwdD4=",
+            "data:,image",
+            "data:image/png;base64",
+            "https://example.com/logo.png",
+        ] {
+            let err = decode_data_uri(uri, 1024).err().unwrap();
+            assert_eq!(err.kind(), ImageFetchErrorKind::InvalidUrl, "{}", uri);
+        }
+    }
+}
@@ -1,22 +1,134 @@
-#[derive(Debug)]
+use std::io::ErrorKind;
+
+#[derive(Debug, Clone)]
 pub enum ImageFetchError {
     Request(String),
+    /// Connection refused, reset or aborted by the peer (same kind as `Request`, but retried)
+    Connection(String),
+    Timeout,
+    HttpStatus(u16),
     TooLarge,
+    TooComplex(String),
     InvalidContentType,
     PrivateIpBlocked(String),
+    HostNotAllowed(String),
     InvalidUrl(String),
+    CircuitOpen(String),
+}
+
+/// Coarse classification of fetch failures, used to pick negative cache TTLs and fallbacks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ImageFetchErrorKind {
+    Request,
+    Timeout,
+    HttpStatus,
+    TooLarge,
+    TooComplex,
+    InvalidContentType,
+    PrivateIpBlocked,
+    HostNotAllowed,
+    InvalidUrl,
+    CircuitOpen,
+}
+
+impl ImageFetchError {
+    pub fn kind(&self) -> ImageFetchErrorKind {
+        match self {
+            Self::Request(_) | Self::Connection(_) => ImageFetchErrorKind::Request,
+            Self::Timeout => ImageFetchErrorKind::Timeout,
+            Self::HttpStatus(_) => ImageFetchErrorKind::HttpStatus,
+            Self::TooLarge => ImageFetchErrorKind::TooLarge,
+            Self::TooComplex(_) => ImageFetchErrorKind::TooComplex,
+            Self::InvalidContentType => ImageFetchErrorKind::InvalidContentType,
+            Self::PrivateIpBlocked(_) => ImageFetchErrorKind::PrivateIpBlocked,
+            Self::HostNotAllowed(_) => ImageFetchErrorKind::HostNotAllowed,
+            Self::InvalidUrl(_) => ImageFetchErrorKind::InvalidUrl,
+            Self::CircuitOpen(_) => ImageFetchErrorKind::CircuitOpen,
+        }
+    }
+
+    /// Whether the failure may go away on its own (refused or reset connections, timeouts,
+    /// 408/429/5xx)
+    ///
+    /// Other request errors, such as TLS failures or too many redirects, are permanent.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Connection(_) | Self::Timeout => true,
+            Self::HttpStatus(status) => matches!(status, 408 | 429 | 500..=599),
+            _ => false,
+        }
+    }
+
+    /// Convert a reqwest error, keeping timeouts distinguishable
+    ///
+    /// Errors raised by our own resolver or redirect policy are passed through unchanged.
+    pub fn from_reqwest(e: reqwest::Error) -> Self {
+        let mut source = std::error::Error::source(&e);
+        while let Some(err) = source {
+            if let Some(inner) = err.downcast_ref::<Self>() {
+                return inner.clone();
+            }
+            source = err.source();
+        }
+
+        if e.is_timeout() {
+            Self::Timeout
+        } else if is_connection_error(&e) {
+            Self::Connection(e.to_string())
+        } else {
+            Self::Request(e.to_string())
+        }
+    }
 }
 
 impl std::fmt::Display for ImageFetchError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Request(msg) => write!(f, "Request error: {}", msg),
+            Self::Connection(msg) => write!(f, "Connection error: {}", msg),
+            Self::Timeout => write!(f, "Request timed out"),
+            Self::HttpStatus(status) => write!(f, "Request error: HTTP {}", status),
             Self::TooLarge => write!(f, "Image exceeds maximum size"),
+            Self::TooComplex(msg) => write!(f, "Image too complex: {}", msg),
             Self::InvalidContentType => write!(f, "Invalid image content type"),
             Self::PrivateIpBlocked(msg) => write!(f, "SSRF protection: {}", msg),
+            Self::HostNotAllowed(msg) => write!(f, "Host not allowed: {}", msg),
             Self::InvalidUrl(msg) => write!(f, "Invalid URL: {}", msg),
+            Self::CircuitOpen(host) => write!(f, "Host {} is failing, not retrying yet", host),
         }
     }
 }
 
 impl std::error::Error for ImageFetchError {}
+
+/// Whether an I/O error in the error's source chain shows the connection was refused or dropped
+fn is_connection_error(e: &reqwest::Error) -> bool {
+    let mut source = std::error::Error::source(e);
+    while let Some(err) = source {
+        if let Some(io) = err.downcast_ref::<std::io::Error>() {
+            return matches!(
+                io.kind(),
+                ErrorKind::ConnectionRefused
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::BrokenPipe
+                    | ErrorKind::UnexpectedEof
+            );
+        }
+        source = err.source();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_connection_failures_are_transient() {
+        assert!(ImageFetchError::Connection("reset".to_string()).is_transient());
+        assert!(ImageFetchError::HttpStatus(503).is_transient());
+        assert!(!ImageFetchError::HttpStatus(404).is_transient());
+        assert!(!ImageFetchError::Request("too many redirects".to_string()).is_transient());
+    }
+}
@@ -1,7 +1,10 @@
 use futures_util::TryStreamExt;
-use reqwest::Client;
+use reqwest::header::{self, HeaderMap};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use std::time::Duration;
 
 use super::error::ImageFetchError;
+use super::freshness::CacheHeaders;
 use super::parse::ParsedUrl;
 
 /// Fetched image bytes
@@ -9,48 +12,115 @@ use super::parse::ParsedUrl;
 pub struct FetchedImage {
     pub bytes: Vec<u8>,
     pub url: String,
+    pub cache: CacheHeaders,
+}
+
+/// Outcome of a conditional request against a cached image
+pub enum Revalidation {
+    /// Origin answered `304 Not Modified`; cached bytes are still valid
+    NotModified(CacheHeaders),
+    /// Origin sent a new representation
+    Modified(FetchedImage),
 }
 
 /// Stage 2: Fetch image via HTTP (SSRF protection handled by custom DNS resolver)
 pub async fn fetch_http(
     parsed: ParsedUrl,
     client: &Client,
+    headers: HeaderMap,
+    timeout: Duration,
     max_size: usize,
 ) -> Result<FetchedImage, ImageFetchError> {
     tracing::info!("Fetching image from URL: {}", parsed.original);
 
-    // Make HTTP request (custom DNS resolver validates IPs)
-    let response = client
+    let request = client
+        .get(parsed.url.as_str())
+        .headers(headers)
+        .timeout(timeout);
+    let response = send(request).await?;
+    read_body(parsed, response, max_size).await
+}
+
+/// Revalidate a cached image with a conditional request (`If-None-Match` / `If-Modified-Since`)
+pub async fn revalidate_http(
+    parsed: ParsedUrl,
+    client: &Client,
+    headers: HeaderMap,
+    timeout: Duration,
+    max_size: usize,
+    cached: &CacheHeaders,
+) -> Result<Revalidation, ImageFetchError> {
+    tracing::debug!("Revalidating cached image: {}", parsed.original);
+
+    let mut request = client
         .get(parsed.url.as_str())
+        .headers(headers)
+        .timeout(timeout);
+    if let Some(etag) = &cached.etag {
+        request = request.header(header::IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &cached.last_modified {
+        request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+    }
+
+    let response = send(request).await?;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        return Ok(Revalidation::NotModified(CacheHeaders::from_headers(
+            response.headers(),
+        )));
+    }
+
+    read_body(parsed, response, max_size)
+        .await
+        .map(Revalidation::Modified)
+}
+
+/// Make HTTP request (custom DNS resolver validates IPs)
+async fn send(request: RequestBuilder) -> Result<Response, ImageFetchError> {
+    let response = request
         .send()
         .await
-        .map_err(|e| ImageFetchError::Request(e.to_string()))?;
+        .map_err(ImageFetchError::from_reqwest)?;
 
-    // Check status
-    if !response.status().is_success() {
-        return Err(ImageFetchError::Request(format!(
-            "HTTP {}",
-            response.status()
-        )));
+    // Check status (304 is only ever returned for conditional requests)
+    let status = response.status();
+    if !status.is_success() && status != StatusCode::NOT_MODIFIED {
+        return Err(ImageFetchError::HttpStatus(status.as_u16()));
     }
 
+    Ok(response)
+}
+
+/// Read a successful response body, enforcing the size limit
+async fn read_body(
+    parsed: ParsedUrl,
+    response: Response,
+    max_size: usize,
+) -> Result<FetchedImage, ImageFetchError> {
     // Check Content-Length if available to avoid downloading large files
-    if let Some(content_length) = response.content_length() {
-        if content_length as usize > max_size {
-            tracing::warn!(
-                "Image from {} exceeds max size (Content-Length): {} > {}",
-                parsed.original,
-                content_length,
-                max_size
-            );
-            return Err(ImageFetchError::TooLarge);
-        }
+    if let Some(content_length) = response.content_length()
+        && content_length as usize > max_size
+    {
+        tracing::warn!(
+            "Image from {} exceeds max size (Content-Length): {} > {}",
+            parsed.original,
+            content_length,
+            max_size
+        );
+        return Err(ImageFetchError::TooLarge);
     }
 
+    let cache = CacheHeaders::from_headers(response.headers());
+
     // Stream response body with size limit enforcement
     let bytes = response
         .bytes_stream()
-        .map_err(|e| ImageFetchError::Request(format!("Failed to read response chunk: {}", e)))
+        .map_err(|e| match ImageFetchError::from_reqwest(e) {
+            ImageFetchError::Request(msg) => {
+                ImageFetchError::Request(format!("Failed to read response chunk: {}", msg))
+            }
+            e => e,
+        })
         .try_fold(Vec::new(), |mut acc, chunk| {
             let url = parsed.original.clone();
             async move {
@@ -75,5 +145,6 @@ pub async fn fetch_http(
     Ok(FetchedImage {
         bytes,
         url: parsed.original,
+        cache,
     })
 }
@@ -0,0 +1,88 @@
+use async_trait::async_trait;
+use std::collections::HashMap;
+#[cfg(debug_assertions)]
+use std::path::Path;
+use std::sync::Arc;
+use url::Url;
+
+use super::error::ImageFetchError;
+use super::fetch::FetchedImage;
+use super::freshness::CacheHeaders;
+use super::source::ImageSource;
+
+/// In-memory image source keyed by host and path, for running without network access
+///
+/// The scheme and query string are ignored, so `https://example.com/logo.png?v=2` is served
+/// from the `example.com/logo.png` entry. Unknown URLs fail with a 404 status.
+#[derive(Clone, Default)]
+pub struct FixtureSource {
+    images: HashMap<String, Arc<Vec<u8>>>,
+}
+
+impl FixtureSource {
+    /// Load every file below `dir`, laid out as `<dir>/<host>/<path>`
+    #[cfg(debug_assertions)]
+    pub fn from_dir(dir: &Path) -> std::io::Result<Self> {
+        let mut fixtures = Self::default();
+        let mut pending = vec![dir.to_path_buf()];
+
+        while let Some(current) = pending.pop() {
+            for entry in std::fs::read_dir(&current)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+
+                let Ok(relative) = path.strip_prefix(dir) else {
+                    continue;
+                };
+                let key = relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                fixtures.insert(key, std::fs::read(&path)?);
+            }
+        }
+
+        tracing::info!(
+            "Serving {} image fixture(s) from {} instead of the network",
+            fixtures.images.len(),
+            dir.display()
+        );
+        Ok(fixtures)
+    }
+
+    /// Serve `bytes` for URLs with the given `host/path` key
+    pub fn insert(&mut self, key: impl Into<String>, bytes: Vec<u8>) {
+        self.images.insert(key.into(), Arc::new(bytes));
+    }
+
+    fn key(url: &str) -> Result<String, ImageFetchError> {
+        let parsed = Url::parse(url)
+            .map_err(|e| ImageFetchError::InvalidUrl(format!("Failed to parse URL: {}", e)))?;
+        let host = parsed
+            .host_str()
+            .ok_or_else(|| ImageFetchError::InvalidUrl("URL has no host".to_string()))?;
+        Ok(format!("{}{}", host, parsed.path()))
+    }
+}
+
+#[async_trait]
+impl ImageSource for FixtureSource {
+    async fn fetch(&self, url: &str) -> Result<FetchedImage, ImageFetchError> {
+        let key = Self::key(url)?;
+        let bytes = self.images.get(&key).ok_or_else(|| {
+            tracing::warn!("No image fixture for {}", url);
+            ImageFetchError::HttpStatus(404)
+        })?;
+
+        tracing::debug!("Serving image fixture {} for {}", key, url);
+        Ok(FetchedImage {
+            bytes: bytes.to_vec(),
+            url: url.to_string(),
+            cache: CacheHeaders::default(),
+        })
+    }
+}
@@ -0,0 +1,285 @@
+use reqwest::header::{self, HeaderMap};
+use std::time::{Duration, SystemTime};
+
+/// Cache-relevant response headers from an image origin
+#[derive(Debug, Clone, Default)]
+pub struct CacheHeaders {
+    /// `ETag` validator for conditional requests
+    pub etag: Option<String>,
+    /// `Last-Modified` validator for conditional requests
+    pub last_modified: Option<String>,
+    /// Freshness lifetime advertised by `Cache-Control` or `Expires`
+    pub max_age: Option<Duration>,
+    /// `Cache-Control: no-cache`, requiring revalidation before every reuse
+    pub no_cache: bool,
+    /// `Cache-Control: no-store`, forbidding the response from being cached at all
+    pub no_store: bool,
+}
+
+impl CacheHeaders {
+    /// Extract validators and freshness lifetime from response headers
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let get = |name| {
+            headers
+                .get(name)
+                .and_then(|v: &header::HeaderValue| v.to_str().ok())
+                .map(|v| v.to_string())
+        };
+
+        Self {
+            etag: get(header::ETAG),
+            last_modified: get(header::LAST_MODIFIED),
+            max_age: freshness_lifetime(headers),
+            no_cache: has_directive(headers, "no-cache"),
+            no_store: has_directive(headers, "no-store"),
+        }
+    }
+
+    /// Merge headers from a `304 Not Modified` response into the stored ones
+    ///
+    /// Origins may omit validators and freshness headers on 304 responses, so existing ones
+    /// are kept unless the response sends its own.
+    pub fn refresh(&self, revalidated: CacheHeaders) -> Self {
+        let has_freshness = revalidated.max_age.is_some() || revalidated.no_cache;
+
+        Self {
+            etag: revalidated.etag.or_else(|| self.etag.clone()),
+            last_modified: revalidated
+                .last_modified
+                .or_else(|| self.last_modified.clone()),
+            max_age: if has_freshness {
+                revalidated.max_age
+            } else {
+                self.max_age
+            },
+            no_cache: if has_freshness {
+                revalidated.no_cache
+            } else {
+                self.no_cache
+            },
+            no_store: revalidated.no_store,
+        }
+    }
+}
+
+/// Bounds applied to origin-provided freshness lifetimes
+#[derive(Debug, Clone, Copy)]
+pub struct TtlBounds {
+    /// TTL used when the origin sends no cache headers
+    pub default: Duration,
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl TtlBounds {
+    /// Clamp an origin freshness lifetime to the configured bounds
+    ///
+    /// `no-cache` responses are stale immediately, whatever the minimum, so they are
+    /// revalidated on their next use.
+    pub fn ttl(&self, headers: &CacheHeaders) -> Duration {
+        if headers.no_cache {
+            return Duration::ZERO;
+        }
+        headers
+            .max_age
+            .unwrap_or(self.default)
+            .clamp(self.min, self.max)
+    }
+}
+
+/// Compute the freshness lifetime from `Cache-Control` and `Expires`
+///
+/// `s-maxage` wins over `max-age` since we act as a shared cache, and both win over `Expires`.
+/// `no-cache` and `no-store` are handled separately (see `TtlBounds::ttl` and `ImageFetcher`).
+/// The `Age` header is subtracted so content that sat in an upstream cache is not kept too long.
+fn freshness_lifetime(headers: &HeaderMap) -> Option<Duration> {
+    let mut max_age: Option<u64> = None;
+    let mut s_maxage: Option<u64> = None;
+
+    for value in headers.get_all(header::CACHE_CONTROL) {
+        let Ok(value) = value.to_str() else {
+            continue;
+        };
+
+        for directive in value.split(',') {
+            let directive = directive.trim().to_ascii_lowercase();
+            let (name, arg) = match directive.split_once('=') {
+                Some((name, arg)) => (name.trim(), Some(arg.trim().trim_matches('"'))),
+                None => (directive.as_str(), None),
+            };
+
+            match (name, arg) {
+                ("max-age", Some(secs)) => max_age = secs.parse().ok(),
+                ("s-maxage", Some(secs)) => s_maxage = secs.parse().ok(),
+                _ => {}
+            }
+        }
+    }
+
+    let age = headers
+        .get(header::AGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .unwrap_or(0);
+
+    if let Some(secs) = s_maxage.or(max_age) {
+        return Some(Duration::from_secs(secs.saturating_sub(age)));
+    }
+
+    let expires = headers
+        .get(header::EXPIRES)
+        .and_then(|v| v.to_str().ok())
+        .map(httpdate::parse_http_date)?;
+
+    // Invalid dates (commonly "0" or "-1") mean already expired
+    let Ok(expires) = expires else {
+        return Some(Duration::ZERO);
+    };
+
+    let date = headers
+        .get(header::DATE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| httpdate::parse_http_date(v).ok())
+        .unwrap_or_else(SystemTime::now);
+
+    Some(expires.duration_since(date).unwrap_or(Duration::ZERO))
+}
+
+/// Check whether `Cache-Control` has a directive, e.g. `no-store`
+fn has_directive(headers: &HeaderMap, name: &str) -> bool {
+    headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|directive| {
+            let directive = directive
+                .split_once('=')
+                .map_or(directive, |(name, _)| name);
+            directive.trim().eq_ignore_ascii_case(name)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.append(*name, HeaderValue::from_str(value).unwrap());
+        }
+        map
+    }
+
+    fn lifetime(pairs: &[(&'static str, &str)]) -> Option<Duration> {
+        freshness_lifetime(&headers(pairs))
+    }
+
+    const BOUNDS: TtlBounds = TtlBounds {
+        default: Duration::from_secs(3600),
+        min: Duration::from_secs(60),
+        max: Duration::from_secs(86400),
+    };
+
+    #[test]
+    fn prefers_s_maxage_over_max_age() {
+        let secs = |s| Some(Duration::from_secs(s));
+        assert_eq!(lifetime(&[("cache-control", "max-age=600")]), secs(600));
+        assert_eq!(
+            lifetime(&[("cache-control", "max-age=600, s-maxage=120")]),
+            secs(120)
+        );
+        assert_eq!(
+            lifetime(&[
+                ("cache-control", "s-maxage=120"),
+                ("cache-control", "max-age=600")
+            ]),
+            secs(120)
+        );
+    }
+
+    #[test]
+    fn subtracts_age() {
+        let cache_control = ("cache-control", "max-age=600");
+        assert_eq!(
+            lifetime(&[cache_control, ("age", "100")]),
+            Some(Duration::from_secs(500))
+        );
+        assert_eq!(
+            lifetime(&[cache_control, ("age", "900")]),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn falls_back_to_expires() {
+        let date = ("date", "Sun, 18 Oct 2026 12:00:00 GMT");
+        assert_eq!(
+            lifetime(&[date, ("expires", "Sun, 18 Oct 2026 12:05:00 GMT")]),
+            Some(Duration::from_secs(300))
+        );
+        assert_eq!(lifetime(&[date, ("expires", "0")]), Some(Duration::ZERO));
+        assert_eq!(
+            lifetime(&[
+                date,
+                ("expires", "Sun, 18 Oct 2026 12:05:00 GMT"),
+                ("cache-control", "max-age=60"),
+            ]),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(lifetime(&[date]), None);
+    }
+
+    #[test]
+    fn no_cache_is_stale_immediately() {
+        let cached = CacheHeaders::from_headers(&headers(&[("cache-control", "no-cache")]));
+        assert!(cached.no_cache);
+        assert_eq!(BOUNDS.ttl(&cached), Duration::ZERO);
+
+        let cached = CacheHeaders::from_headers(&headers(&[("cache-control", "max-age=0")]));
+        assert_eq!(BOUNDS.ttl(&cached), BOUNDS.min);
+        assert_eq!(BOUNDS.ttl(&CacheHeaders::default()), BOUNDS.default);
+    }
+
+    #[test]
+    fn detects_no_store() {
+        let no_store = |value| has_directive(&headers(&[("cache-control", value)]), "no-store");
+        assert!(no_store("no-store"));
+        assert!(no_store("public, NO-STORE"));
+        assert!(!no_store("max-age=60"));
+        assert!(!no_store("no-storage"));
+        assert!(!has_directive(&HeaderMap::new(), "no-store"));
+    }
+
+    #[test]
+    fn refresh_keeps_validators_and_lifetime() {
+        let stored = CacheHeaders::from_headers(&headers(&[
+            ("etag", "\"v1\""),
+            ("last-modified", "Sun, 18 Oct 2026 12:00:00 GMT"),
+            ("cache-control", "max-age=600"),
+        ]));
+
+        let refreshed = stored.refresh(CacheHeaders::default());
+        assert_eq!(refreshed.etag.as_deref(), Some("\"v1\""));
+        assert_eq!(
+            refreshed.last_modified.as_deref(),
+            Some("Sun, 18 Oct 2026 12:00:00 GMT")
+        );
+        assert_eq!(refreshed.max_age, Some(Duration::from_secs(600)));
+
+        let revalidated = CacheHeaders::from_headers(&headers(&[
+            ("etag", "\"v2\""),
+            ("cache-control", "max-age=60"),
+        ]));
+        let refreshed = stored.refresh(revalidated);
+        assert_eq!(refreshed.etag.as_deref(), Some("\"v2\""));
+        assert_eq!(refreshed.max_age, Some(Duration::from_secs(60)));
+
+        let revalidated = CacheHeaders::from_headers(&headers(&[("cache-control", "no-cache")]));
+        let refreshed = stored.refresh(revalidated);
+        assert!(refreshed.no_cache);
+        assert_eq!(BOUNDS.ttl(&refreshed), Duration::ZERO);
+    }
+}
@@ -1,93 +1,528 @@
-use reqwest::Client;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+mod asset;
 mod cache;
+mod circuit;
+mod data;
 mod error;
 mod fetch;
+#[cfg(any(test, debug_assertions))]
+mod fixture;
+mod freshness;
 mod parse;
+mod policy;
+mod resize;
 mod resolver;
+mod retry;
+mod sanitize;
+mod source;
 mod validate;
 
-pub use error::ImageFetchError;
+pub use data::{describe as describe_source, is_data_uri};
+pub use error::{ImageFetchError, ImageFetchErrorKind};
+pub use resize::TargetSize;
+pub use source::ImageSource;
 pub use validate::ValidatedImage;
 
-use cache::ImageCache;
-use resolver::GlobalResolver;
+use crate::config::ImageSettings;
+use asset::AssetStore;
+use cache::{CachedImage, FailureCache, ImageCache, NegativeTtls};
+use fetch::Revalidation;
+#[cfg(any(test, debug_assertions))]
+use fixture::FixtureSource;
+use freshness::TtlBounds;
+use policy::HostPolicy;
+use resolver::{NameLookup, StaticLookup, SystemLookup};
+use source::HttpSource;
+use validate::ImageLimits;
 
+#[derive(Clone)]
 pub struct ImageFetcher {
-    client: Client,
+    source: Arc<dyn ImageSource>,
     cache: ImageCache,
+    failures: FailureCache,
+    assets: AssetStore,
+    ttl: TtlBounds,
     max_size: usize,
-    allow_http: bool,
+    limits: ImageLimits,
+    /// URLs with a background revalidation in flight
+    revalidating: Arc<Mutex<HashSet<String>>>,
 }
 
 impl ImageFetcher {
-    pub fn new(
-        connect_timeout_secs: u64,
-        total_timeout_secs: u64,
-        max_size: usize,
-        cache_size: u64,
-        cache_ttl_secs: u64,
-        max_redirects: usize,
-        allow_http: bool,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
-        // Create global-only DNS resolver (SSRF protection)
-        let resolver = GlobalResolver::new()?;
+    /// Create a fetcher for the configured image source
+    ///
+    /// Images are fetched over HTTP(S), or in debug builds served from the fixtures directory
+    /// if one is set.
+    pub fn new(settings: &ImageSettings) -> Result<Self, Box<dyn std::error::Error>> {
+        #[cfg(debug_assertions)]
+        if let Some(dir) = &settings.fixtures_dir {
+            return Self::with_source(settings, Arc::new(FixtureSource::from_dir(dir)?));
+        }
 
-        // Create HTTP client with custom resolver and timeouts
-        let client = Client::builder()
-            .timeout(Duration::from_secs(total_timeout_secs))
-            .connect_timeout(Duration::from_secs(connect_timeout_secs))
-            .redirect(reqwest::redirect::Policy::limited(max_redirects))
-            .dns_resolver(std::sync::Arc::new(resolver))
-            .build()?;
+        let policy = Arc::new(HostPolicy::new(
+            &settings.allowed_hosts,
+            &settings.denied_hosts,
+            &settings.ssrf_allowed_networks,
+        )?);
 
-        // Initialize cache
-        let cache = ImageCache::new(cache_size, cache_ttl_secs);
+        let mut lookup: Arc<dyn NameLookup> = Arc::new(SystemLookup::new(&settings.dns_servers)?);
+        if !settings.host_overrides.is_empty() {
+            lookup = Arc::new(StaticLookup::new(&settings.host_overrides, lookup)?);
+        }
 
+        let source = HttpSource::new(settings, policy, lookup)?;
         tracing::info!(
             "ImageFetcher initialized with GlobalResolver (SSRF protection), HTTP allowed: {}",
-            allow_http
+            settings.allow_http
         );
 
+        Self::with_source(settings, Arc::new(source))
+    }
+
+    /// Create a fetcher that retrieves images from `source`
+    pub fn with_source(
+        settings: &ImageSettings,
+        source: Arc<dyn ImageSource>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        // Initialize cache, keeping entries past their freshness for stale-while-revalidate
+        let cache = ImageCache::new(settings.cache_size, settings.cache_stale_secs);
+
+        // Short-lived negative entries so dead URLs fall back immediately
+        let failures = FailureCache::new(
+            settings.cache_size,
+            NegativeTtls {
+                request: Duration::from_secs(settings.negative_ttl_request_secs),
+                timeout: Duration::from_secs(settings.negative_ttl_timeout_secs),
+                http_status: Duration::from_secs(settings.negative_ttl_status_secs),
+                invalid: Duration::from_secs(settings.negative_ttl_invalid_secs),
+                blocked: Duration::from_secs(settings.negative_ttl_blocked_secs),
+            },
+        );
+
+        let assets = AssetStore::new(settings.assets_dir.as_deref(), settings.cache_size)?;
+
+        let ttl = TtlBounds {
+            default: Duration::from_secs(settings.cache_ttl_secs),
+            min: Duration::from_secs(settings.cache_min_ttl_secs),
+            max: Duration::from_secs(settings.cache_max_ttl_secs.max(settings.cache_min_ttl_secs)),
+        };
+
         Ok(Self {
-            client,
+            source,
             cache,
-            max_size,
-            allow_http,
+            failures,
+            assets,
+            ttl,
+            max_size: settings.max_size_bytes,
+            limits: ImageLimits {
+                max_pixels: settings.max_pixels,
+                svg_max_nodes: settings.svg_max_nodes,
+                svg_max_depth: settings.svg_max_depth,
+            },
+            revalidating: Arc::new(Mutex::new(HashSet::new())),
         })
     }
 
     /// Fetch image with MIME type detection
     ///
     /// Pipeline stages:
-    /// 1. Check cache (raw bytes, re-detect MIME type)
-    /// 2. Parse URL + validate direct IPs
-    /// 3. HTTP fetch with streaming size limit (SSRF protection via GlobalResolver)
-    /// 4. Validate content-type and detect MIME type
-    /// 5. Store in cache (raw bytes only)
-    pub async fn fetch_image(&self, url: &str) -> Result<ValidatedImage, ImageFetchError> {
+    /// 1. Check cache (validated bytes, MIME type and size); stale entries are served
+    ///    while a conditional request revalidates them in the background
+    /// 2. Check negative cache (recent failures are returned without refetching)
+    /// 3. Fetch from the image source (for HTTP: parse URL + validate direct IPs, then fetch
+    ///    with streaming size limit and SSRF protection via GlobalResolver)
+    /// 4. Enforce the size limit for sources that do not stream
+    /// 5. Validate content-type, detect MIME type and probe dimensions (decompression bombs)
+    /// 6. Store in cache (raw bytes and validators, TTL from origin cache headers, nothing
+    ///    for `no-store` responses), or in the negative cache on failure
+    /// 7. Downscale raster images to the target slot size, caching the rendition
+    ///
+    /// `data:` URIs skip the caches and network stages but are validated and downscaled alike;
+    /// `asset:` paths are read from the assets directory and cached for the process lifetime.
+    pub async fn fetch_image(
+        &self,
+        url: &str,
+        target: Option<TargetSize>,
+    ) -> Result<ValidatedImage, ImageFetchError> {
+        if data::is_data_uri(url) {
+            let fetched = data::decode_data_uri(url, self.max_size)?;
+            return self.validate_inline(fetched, target);
+        }
+
+        if let Some(path) = asset::asset_path(url) {
+            let cached = self.assets.load(path, self.max_size, &self.limits).await?;
+            return Ok(Self::rendition(&cached, target));
+        }
+
         // Stage 0: Check cache first
-        if let Some(cached_bytes) = self.cache.get(url).await {
-            tracing::debug!("Cache hit for URL: {}", url);
-            // Re-detect MIME type from cached bytes
-            let mime_type = infer::get(&cached_bytes)
-                .map(|k| k.mime_type().to_string())
-                .unwrap_or_else(|| "image/png".to_string());
-            return Ok(ValidatedImage {
-                bytes: cached_bytes,
-                mime_type,
-            });
+        let cached = match self.cache.get(url).await {
+            Some(cached) => {
+                if cached.is_fresh() {
+                    tracing::debug!("Cache hit for URL: {}", url);
+                } else {
+                    tracing::debug!("Serving stale cache entry for URL: {}", url);
+                    self.spawn_revalidation(url, cached.clone());
+                }
+                cached
+            }
+            None => {
+                if let Some(error) = self.failures.get(url).await {
+                    tracing::debug!("Negative cache hit for URL: {} ({})", url, error);
+                    return Err(error);
+                }
+
+                match self.fetch_uncached(url).await {
+                    Ok(cached) => cached,
+                    Err(e) => {
+                        self.failures.insert(url.to_string(), e.clone()).await;
+                        return Err(e);
+                    }
+                }
+            }
+        };
+
+        Ok(Self::rendition(&cached, target))
+    }
+
+    /// Validate uploaded image bytes exactly like fetched ones
+    pub fn load_upload(
+        &self,
+        bytes: Vec<u8>,
+        name: &str,
+        target: Option<TargetSize>,
+    ) -> Result<ValidatedImage, ImageFetchError> {
+        if bytes.len() > self.max_size {
+            tracing::warn!(
+                "Uploaded {} exceeds max size: {} > {}",
+                name,
+                bytes.len(),
+                self.max_size
+            );
+            return Err(ImageFetchError::TooLarge);
+        }
+
+        let fetched = fetch::FetchedImage {
+            bytes,
+            url: format!("upload:{}", name),
+            cache: freshness::CacheHeaders::default(),
+        };
+        self.validate_inline(fetched, target)
+    }
+
+    /// Validate and downscale image bytes that did not come from the network (never cached)
+    fn validate_inline(
+        &self,
+        fetched: fetch::FetchedImage,
+        target: Option<TargetSize>,
+    ) -> Result<ValidatedImage, ImageFetchError> {
+        let validated = validate::validate_content_type(fetched, &self.limits)?;
+        Ok(target
+            .and_then(|target| resize::downscale(&validated, target))
+            .unwrap_or(validated))
+    }
+
+    async fn fetch_uncached(&self, url: &str) -> Result<CachedImage, ImageFetchError> {
+        let fetched = self.source.fetch(url).await?;
+        self.check_size(&fetched)?;
+        let headers = fetched.cache.clone();
+        let validated = validate::validate_content_type(fetched, &self.limits)?;
+
+        Ok(self.store(url, validated, headers).await)
+    }
+
+    /// Reject images over the size limit, whichever source they came from
+    fn check_size(&self, fetched: &fetch::FetchedImage) -> Result<(), ImageFetchError> {
+        if fetched.bytes.len() > self.max_size {
+            tracing::warn!(
+                "Image from {} exceeds max size: {} > {}",
+                fetched.url,
+                fetched.bytes.len(),
+                self.max_size
+            );
+            return Err(ImageFetchError::TooLarge);
+        }
+        Ok(())
+    }
+
+    /// Get the cached image downscaled for `target`, rendering it on first use
+    fn rendition(cached: &CachedImage, target: Option<TargetSize>) -> ValidatedImage {
+        let original = Self::from_cache(cached);
+        let Some(target) = target else {
+            return original;
+        };
+
+        if let Some(variant) = cached.variants.lock().unwrap().get(&target) {
+            return variant.clone();
+        }
+
+        match resize::downscale(&original, target) {
+            Some(resized) => {
+                cached
+                    .variants
+                    .lock()
+                    .unwrap()
+                    .insert(target, resized.clone());
+                resized
+            }
+            None => original,
+        }
+    }
+
+    /// Build a validated image from a cache entry
+    fn from_cache(cached: &CachedImage) -> ValidatedImage {
+        ValidatedImage {
+            bytes: (*cached.bytes).clone(),
+            mime_type: cached.mime_type.clone(),
+            size: cached.size,
+        }
+    }
+
+    async fn store(
+        &self,
+        url: &str,
+        image: ValidatedImage,
+        headers: freshness::CacheHeaders,
+    ) -> CachedImage {
+        let ttl = self.ttl.ttl(&headers);
+        let no_store = headers.no_store;
+        let entry = CachedImage::new(image, headers, ttl);
+        if no_store {
+            tracing::debug!("Not caching {}: origin sent no-store", url);
+            self.cache.invalidate(url).await;
+        } else {
+            tracing::debug!("Caching {} for {:?}", url, ttl);
+            self.cache.insert(url.to_string(), entry.clone()).await;
         }
+        entry
+    }
+
+    /// Revalidate a stale entry in the background, at most once per URL at a time
+    fn spawn_revalidation(&self, url: &str, cached: CachedImage) {
+        if !self.revalidating.lock().unwrap().insert(url.to_string()) {
+            return;
+        }
+
+        let fetcher = self.clone();
+        let url = url.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = fetcher.revalidate(&url, cached).await {
+                tracing::warn!("Failed to revalidate {}: {} - keeping stale entry", url, e);
+            }
+            fetcher.revalidating.lock().unwrap().remove(&url);
+        });
+    }
+
+    async fn revalidate(&self, url: &str, cached: CachedImage) -> Result<(), ImageFetchError> {
+        match self.source.revalidate(url, &cached.headers).await? {
+            Revalidation::NotModified(headers) => {
+                tracing::debug!("Cached image still valid: {}", url);
+                let headers = cached.headers.refresh(headers);
+                if headers.no_store {
+                    self.cache.invalidate(url).await;
+                    return Ok(());
+                }
+                let ttl = self.ttl.ttl(&headers);
+                self.cache
+                    .insert(url.to_string(), cached.refreshed(headers, ttl))
+                    .await;
+            }
+            Revalidation::Modified(fetched) => {
+                tracing::info!("Cached image changed at origin: {}", url);
+                self.check_size(&fetched)?;
+                let headers = fetched.cache.clone();
+                let validated = validate::validate_content_type(fetched, &self.limits)?;
+                self.store(url, validated, headers).await;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, FallbackPolicy, ImageFallbackBehavior};
+    use async_trait::async_trait;
+    use clap::Parser;
+    use std::net::IpAddr;
+
+    /// Lookup resolving every host to the same addresses, without touching the network
+    struct StubLookup(Vec<IpAddr>);
+
+    #[async_trait]
+    impl NameLookup for StubLookup {
+        async fn lookup(&self, _host: &str) -> std::io::Result<Vec<IpAddr>> {
+            Ok(self.0.clone())
+        }
+    }
+
+    fn settings() -> ImageSettings {
+        Config::try_parse_from(["ogis"]).unwrap().image
+    }
+
+    fn png() -> Vec<u8> {
+        tiny_skia::Pixmap::new(4, 4).unwrap().encode_png().unwrap()
+    }
+
+    fn fixture_fetcher(settings: &ImageSettings) -> ImageFetcher {
+        let mut source = FixtureSource::default();
+        source.insert("images.example.com/logo.png", png());
+        ImageFetcher::with_source(settings, Arc::new(source)).unwrap()
+    }
+
+    /// Fetcher going through the HTTP source, with every host resolving to `ip`
+    fn http_fetcher(ip: &str) -> ImageFetcher {
+        let settings = settings();
+        let policy = Arc::new(HostPolicy::default());
+        let lookup = Arc::new(StubLookup(vec![ip.parse().unwrap()]));
+        let source = HttpSource::new(&settings, policy, lookup).unwrap();
+        ImageFetcher::with_source(&settings, Arc::new(source)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn serves_fixture_images() {
+        let fetcher = fixture_fetcher(&settings());
+        let image = fetcher
+            .fetch_image("https://images.example.com/logo.png?v=2", None)
+            .await
+            .unwrap();
+        assert_eq!(image.mime_type, "image/png");
+        assert_eq!(image.size, Some((4.0, 4.0)));
+    }
+
+    #[test]
+    fn rejects_network_patterns_with_a_proxy() {
+        let mut settings = settings();
+        settings.proxy = Some("http://10.0.0.2:3128".to_string());
+        let lookup = Arc::new(StubLookup(vec![]));
+        let source = |allow: &[&str], deny: &[&str]| {
+            let allow: Vec<_> = allow.iter().map(|p| p.to_string()).collect();
+            let deny: Vec<_> = deny.iter().map(|p| p.to_string()).collect();
+            let policy = Arc::new(HostPolicy::new(&allow, &deny, &[]).unwrap());
+            HttpSource::new(&settings, policy, lookup.clone())
+        };
+
+        assert!(source(&["*.example.com"], &["internal.example.com"]).is_ok());
+        assert!(source(&["203.0.113.0/24"], &[]).is_err());
+        assert!(source(&[], &["10.0.0.0/8"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_redirects_to_denied_hosts() {
+        use axum::response::Redirect;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let app = axum::Router::new()
+            .route(
+                "/logo.png",
+                axum::routing::get(move || async move {
+                    Redirect::temporary(&format!("http://denied.example.com:{}/logo.png", port))
+                }),
+            )
+            .route(
+                "/moved.png",
+                axum::routing::get(move || async move {
+                    Redirect::temporary(&format!("http://cdn.example.com:{}/cdn.png", port))
+                }),
+            )
+            .route("/cdn.png", axum::routing::get(|| async { png() }));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let mut settings = settings();
+        settings.allow_http = true;
+        let policy = HostPolicy::new(
+            &[],
+            &["denied.example.com".to_string()],
+            &["127.0.0.0/8".to_string()],
+        )
+        .unwrap();
+        let lookup = Arc::new(StubLookup(vec!["127.0.0.1".parse().unwrap()]));
+        let source = HttpSource::new(&settings, Arc::new(policy), lookup).unwrap();
+
+        let url = format!("http://images.example.com:{}/logo.png", port);
+        let err = source.fetch(&url).await.err().unwrap();
+        assert!(matches!(err, ImageFetchError::HostNotAllowed(_)), "{}", err);
+
+        let url = format!("http://images.example.com:{}/moved.png", port);
+        let fetched = source.fetch(&url).await.unwrap();
+        assert_eq!(fetched.bytes, png());
+    }
+
+    #[tokio::test]
+    async fn blocks_private_ip_literals() {
+        let fetcher = http_fetcher("93.184.216.34");
+        for url in ["https://127.0.0.1/logo.png", "https://10.0.0.1/logo.png"] {
+            let err = fetcher.fetch_image(url, None).await.err().unwrap();
+            assert_eq!(err.kind(), ImageFetchErrorKind::PrivateIpBlocked, "{}", url);
+        }
+    }
+
+    #[tokio::test]
+    async fn blocks_hosts_resolving_to_private_ips() {
+        let fetcher = http_fetcher("192.168.1.10");
+        let err = fetcher
+            .fetch_image("https://images.example.com/logo.png", None)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ImageFetchErrorKind::PrivateIpBlocked);
+    }
+
+    #[tokio::test]
+    async fn rejects_oversize_images() {
+        let mut settings = settings();
+        settings.max_size_bytes = png().len() - 1;
+        let fetcher = fixture_fetcher(&settings);
+
+        let err = fetcher
+            .fetch_image("https://images.example.com/logo.png", None)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ImageFetchErrorKind::TooLarge);
+        let err = fetcher.load_upload(png(), "logo", None).err().unwrap();
+        assert_eq!(err.kind(), ImageFetchErrorKind::TooLarge);
+    }
 
-        let parsed = parse::parse_url(url, self.allow_http)?;
-        let fetched = fetch::fetch_http(parsed, &self.client, self.max_size).await?;
-        let validated = validate::validate_content_type(fetched)?;
+    #[tokio::test]
+    async fn picks_fallback_by_error_kind() {
+        let mut settings = settings();
+        settings.fallback = ImageFallbackBehavior::Skip;
+        settings.fallback_by_error = vec!["private-ip-blocked=error".to_string()];
+        let fallback = FallbackPolicy::from_settings(&settings).unwrap();
 
-        self.cache
-            .insert(url.to_string(), validated.bytes.clone())
-            .await;
+        let missing = fixture_fetcher(&settings)
+            .fetch_image("https://images.example.com/missing.png", None)
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(
+            fallback.behavior(missing.kind(), None),
+            ImageFallbackBehavior::Skip
+        ));
 
-        Ok(validated)
+        let blocked = http_fetcher("10.1.2.3")
+            .fetch_image("https://images.example.com/logo.png", None)
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(
+            fallback.behavior(blocked.kind(), None),
+            ImageFallbackBehavior::Error
+        ));
+        // Requests cannot loosen a fallback the operator set for an error kind
+        assert!(matches!(
+            fallback.behavior(blocked.kind(), Some(ImageFallbackBehavior::Skip)),
+            ImageFallbackBehavior::Error
+        ));
+        assert!(matches!(
+            fallback.behavior(missing.kind(), Some(ImageFallbackBehavior::Error)),
+            ImageFallbackBehavior::Error
+        ));
     }
 }
@@ -1,7 +1,8 @@
 use std::net::IpAddr;
-use url::Url;
+use url::{Host, Url};
 
 use super::error::ImageFetchError;
+use super::policy::HostPolicy;
 
 /// Parsed and validated URL
 #[derive(Debug, Clone)]
@@ -10,8 +11,14 @@ pub struct ParsedUrl {
     pub original: String,
 }
 
-/// Stage 1: Parse and validate URL scheme + check for direct IPs
-pub fn parse_url(url: &str, allow_http: bool) -> Result<ParsedUrl, ImageFetchError> {
+/// Stage 1: Parse and validate URL scheme + check host policy and direct IPs
+///
+/// Also applied to every redirect hop.
+pub fn parse_url(
+    url: &str,
+    allow_http: bool,
+    policy: &HostPolicy,
+) -> Result<ParsedUrl, ImageFetchError> {
     // Parse URL
     let parsed = Url::parse(url)
         .map_err(|e| ImageFetchError::InvalidUrl(format!("Failed to parse URL: {}", e)))?;
@@ -37,18 +44,30 @@ pub fn parse_url(url: &str, allow_http: bool) -> Result<ParsedUrl, ImageFetchErr
         }
     }
 
+    // Check configured host allow/deny lists
+    let host = parsed
+        .host_str()
+        .ok_or_else(|| ImageFetchError::InvalidUrl("URL has no host".to_string()))?;
+    if let Err(e) = policy.check_host(host) {
+        tracing::warn!("Blocked image URL by host policy: {} ({})", url, e);
+        return Err(e);
+    }
+
     // SSRF Protection: Check if URL contains a direct IP address
-    if let Some(host) = parsed.host_str() {
-        if let Ok(ip) = host.parse::<IpAddr>() {
-            if !ip_rfc::global(&ip) {
-                tracing::warn!("Blocked direct private IP in URL: {} ({})", url, ip);
-                return Err(ImageFetchError::PrivateIpBlocked(format!(
-                    "Private IP address {} is not allowed",
-                    ip
-                )));
-            }
-        }
-        // For hostnames, DNS resolution will be validated by SSRFSafeResolver
+    // For hostnames, DNS resolution will be validated by GlobalResolver
+    let direct_ip = match parsed.host() {
+        Some(Host::Ipv4(ip)) => Some(IpAddr::V4(ip)),
+        Some(Host::Ipv6(ip)) => Some(IpAddr::V6(ip)),
+        _ => None,
+    };
+    if let Some(ip) = direct_ip
+        && !policy.is_reachable(&ip)
+    {
+        tracing::warn!("Blocked direct private IP in URL: {} ({})", url, ip);
+        return Err(ImageFetchError::PrivateIpBlocked(format!(
+            "Private IP address {} is not allowed",
+            ip
+        )));
     }
 
     Ok(ParsedUrl {
@@ -0,0 +1,247 @@
+use ipnet::IpNet;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use super::error::ImageFetchError;
+
+/// A host pattern in an allow or deny list
+#[derive(Debug, Clone)]
+pub enum HostPattern {
+    /// Exact hostname, e.g. `cdn.example.com`
+    Exact(String),
+    /// Any subdomain of a domain, e.g. `*.example.com` (not the apex itself)
+    Subdomain(String),
+    /// Network matched against resolved (or literal) IP addresses, e.g. `203.0.113.0/24`
+    Cidr(IpNet),
+}
+
+impl FromStr for HostPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().trim_end_matches('.').to_ascii_lowercase();
+
+        if s.is_empty() {
+            return Err("empty host pattern".to_string());
+        }
+        if let Ok(net) = s.parse::<IpNet>() {
+            return Ok(Self::Cidr(net));
+        }
+        if let Ok(ip) = s.parse::<IpAddr>() {
+            return Ok(Self::Cidr(IpNet::from(ip)));
+        }
+        if let Some(domain) = s.strip_prefix("*.") {
+            return Ok(Self::Subdomain(domain.to_string()));
+        }
+        if s.contains(['*', '/', ':']) {
+            return Err(format!("invalid host pattern: {}", s));
+        }
+
+        Ok(Self::Exact(s))
+    }
+}
+
+impl HostPattern {
+    pub fn matches_host(&self, host: &str) -> bool {
+        match self {
+            Self::Exact(name) => host == name,
+            Self::Subdomain(domain) => host
+                .strip_suffix(domain.as_str())
+                .is_some_and(|prefix| prefix.len() > 1 && prefix.ends_with('.')),
+            Self::Cidr(_) => false,
+        }
+    }
+
+    fn matches_ip(&self, ip: &IpAddr) -> bool {
+        match self {
+            Self::Cidr(net) => net.contains(ip),
+            _ => false,
+        }
+    }
+}
+
+/// Configurable allow and deny lists for image source hosts
+///
+/// Deny patterns always win. When the allow list is empty every host not denied is allowed;
+/// otherwise a host must match an allowed hostname pattern, or all of its addresses must fall
+/// inside an allowed network.
+///
+/// Independently of these lists, only global addresses are reachable unless they fall inside
+/// one of the SSRF exception networks.
+#[derive(Debug, Clone, Default)]
+pub struct HostPolicy {
+    allow: Vec<HostPattern>,
+    deny: Vec<HostPattern>,
+    private_allowed: Vec<IpNet>,
+}
+
+impl HostPolicy {
+    pub fn new(
+        allow: &[String],
+        deny: &[String],
+        private_allowed: &[String],
+    ) -> Result<Self, String> {
+        let parse = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|p| p.parse())
+                .collect::<Result<Vec<HostPattern>, _>>()
+        };
+
+        let private_allowed = private_allowed
+            .iter()
+            .map(|net| match net.parse::<HostPattern>()? {
+                HostPattern::Cidr(net) => Ok(net),
+                _ => Err(format!("invalid SSRF allowed network: {}", net)),
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        for net in &private_allowed {
+            tracing::warn!("SSRF protection disabled for network {}", net);
+        }
+
+        Ok(Self {
+            allow: parse(allow)?,
+            deny: parse(deny)?,
+            private_allowed,
+        })
+    }
+
+    /// Whether any allow or deny pattern is a network, which only resolved addresses can match
+    pub fn has_networks(&self) -> bool {
+        self.allow
+            .iter()
+            .chain(&self.deny)
+            .any(|p| matches!(p, HostPattern::Cidr(_)))
+    }
+
+    /// Whether connecting to `ip` is safe: any global address, or one inside an exception network
+    pub fn is_reachable(&self, ip: &IpAddr) -> bool {
+        ip_rfc::global(ip) || self.private_allowed.iter().any(|net| net.contains(ip))
+    }
+
+    /// Check a URL host before connecting
+    ///
+    /// Hosts that only an allowed network could admit are let through here and decided
+    /// once their addresses are resolved.
+    pub fn check_host(&self, host: &str) -> Result<(), ImageFetchError> {
+        let host = normalize(host);
+
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return self.check_ip(&host, ip);
+        }
+
+        if self.deny.iter().any(|p| p.matches_host(&host)) {
+            return Err(ImageFetchError::HostNotAllowed(format!(
+                "host {} is denied",
+                host
+            )));
+        }
+
+        let allowed = self.allow.is_empty()
+            || self.allow.iter().any(|p| p.matches_host(&host))
+            || self.allow.iter().any(|p| matches!(p, HostPattern::Cidr(_)));
+        if !allowed {
+            return Err(ImageFetchError::HostNotAllowed(format!(
+                "host {} is not in the allow list",
+                host
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Check an address that `host` resolved to
+    pub fn check_ip(&self, host: &str, ip: IpAddr) -> Result<(), ImageFetchError> {
+        let host = normalize(host);
+
+        if self.deny.iter().any(|p| p.matches_ip(&ip)) {
+            return Err(ImageFetchError::HostNotAllowed(format!(
+                "address {} of {} is denied",
+                ip, host
+            )));
+        }
+
+        let allowed = self.allow.is_empty()
+            || self.allow.iter().any(|p| p.matches_host(&host))
+            || self.allow.iter().any(|p| p.matches_ip(&ip));
+        if !allowed {
+            return Err(ImageFetchError::HostNotAllowed(format!(
+                "address {} of {} is not in the allow list",
+                ip, host
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Lowercase and strip IPv6 brackets and a trailing root dot
+fn normalize(host: &str) -> String {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .trim_end_matches('.')
+        .to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(allow: &[&str], deny: &[&str]) -> HostPolicy {
+        let list = |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        HostPolicy::new(&list(allow), &list(deny), &[]).unwrap()
+    }
+
+    #[test]
+    fn subdomain_patterns_exclude_the_apex() {
+        let pattern: HostPattern = "*.example.com".parse().unwrap();
+        assert!(pattern.matches_host("cdn.example.com"));
+        assert!(pattern.matches_host("a.b.example.com"));
+        assert!(!pattern.matches_host("example.com"));
+        assert!(!pattern.matches_host("badexample.com"));
+
+        let policy = policy(&["*.example.com"], &[]);
+        assert!(policy.check_host("cdn.example.com").is_ok());
+        assert!(policy.check_host("example.com").is_err());
+    }
+
+    #[test]
+    fn cidr_patterns_match_addresses() {
+        let policy = policy(&["203.0.113.0/24"], &[]);
+        assert!(
+            policy
+                .check_ip("img.example.com", "203.0.113.7".parse().unwrap())
+                .is_ok()
+        );
+        assert!(
+            policy
+                .check_ip("img.example.com", "198.51.100.7".parse().unwrap())
+                .is_err()
+        );
+        assert!(policy.check_host("203.0.113.7").is_ok());
+        assert!(policy.check_host("[2001:db8::1]").is_err());
+        // Hostnames are decided once resolved
+        assert!(policy.check_host("img.example.com").is_ok());
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let policy = policy(
+            &["*.example.com", "203.0.113.0/24"],
+            &["internal.example.com", "203.0.113.128/25"],
+        );
+        assert!(policy.check_host("cdn.example.com").is_ok());
+        assert!(policy.check_host("internal.example.com").is_err());
+        assert!(policy.check_host("INTERNAL.example.com.").is_err());
+        assert!(
+            policy
+                .check_ip("cdn.example.com", "203.0.113.7".parse().unwrap())
+                .is_ok()
+        );
+        assert!(
+            policy
+                .check_ip("cdn.example.com", "203.0.113.200".parse().unwrap())
+                .is_err()
+        );
+    }
+}
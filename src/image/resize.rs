@@ -0,0 +1,214 @@
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+
+use super::validate::ValidatedImage;
+
+/// Pixel size an image will occupy in the rendered output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TargetSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl TargetSize {
+    /// Target for a slot of the given size in template units, at the given output scale
+    pub fn from_slot(width: f32, height: f32, scale: f32) -> Option<Self> {
+        let width = (width * scale).ceil();
+        let height = (height * scale).ceil();
+        (width >= 1.0 && height >= 1.0).then_some(Self {
+            width: width as u32,
+            height: height as u32,
+        })
+    }
+}
+
+/// Downscale a raster image so it just covers the target size, re-encoded as PNG
+///
+/// Scaling covers rather than fits the target so the result stays sharp whether the slot
+/// later crops or letterboxes it. Returns None when the image is vector, cannot be probed,
+/// is already small enough, or would not get any smaller as a PNG (typically photos stored
+/// as JPEG), in which case the original should be embedded as-is.
+pub fn downscale(image: &ValidatedImage, target: TargetSize) -> Option<ValidatedImage> {
+    if image.mime_type == "image/svg+xml" {
+        return None;
+    }
+
+    let size = imagesize::blob_size(&image.bytes).ok()?;
+    if size.width == 0 || size.height == 0 {
+        return None;
+    }
+
+    let scale = f64::max(
+        target.width as f64 / size.width as f64,
+        target.height as f64 / size.height as f64,
+    );
+    if scale >= 1.0 {
+        return None;
+    }
+
+    let width = ((size.width as f64 * scale).ceil() as u32).max(1);
+    let height = ((size.height as f64 * scale).ceil() as u32).max(1);
+
+    // Let resvg decode and resample the image by drawing it into a pixmap of the new size
+    let svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}"><image width="{w}" height="{h}" preserveAspectRatio="none" href="data:{mime};base64,{data}"/></svg>"#,
+        w = width,
+        h = height,
+        mime = image.mime_type,
+        data = BASE64.encode(&image.bytes),
+    );
+
+    let tree = usvg::Tree::from_str(&svg, &usvg::Options::default())
+        .inspect_err(|e| tracing::warn!("Failed to load image for resizing: {}", e))
+        .ok()?;
+    let mut pixmap = tiny_skia::Pixmap::new(width, height)?;
+    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+
+    let bytes = pixmap
+        .encode_png()
+        .inspect_err(|e| tracing::warn!("Failed to encode resized image: {}", e))
+        .ok()?;
+
+    if bytes.len() >= image.bytes.len() {
+        tracing::debug!(
+            "Keeping {} image at {}x{}: PNG at {}x{} is not smaller ({} >= {} bytes)",
+            image.mime_type,
+            size.width,
+            size.height,
+            width,
+            height,
+            bytes.len(),
+            image.bytes.len()
+        );
+        return None;
+    }
+
+    tracing::info!(
+        "Downscaled {} image from {}x{} to {}x{} ({} -> {} bytes)",
+        image.mime_type,
+        size.width,
+        size.height,
+        width,
+        height,
+        image.bytes.len(),
+        bytes.len()
+    );
+
+    Some(ValidatedImage {
+        bytes,
+        mime_type: "image/png".to_string(),
+        size: Some((width as f32, height as f32)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(bytes: Vec<u8>) -> ValidatedImage {
+        ValidatedImage {
+            bytes,
+            mime_type: "image/png".to_string(),
+            size: None,
+        }
+    }
+
+    fn target(width: u32, height: u32) -> TargetSize {
+        TargetSize { width, height }
+    }
+
+    /// Grayscale noise PNG with uncompressed deflate blocks, one byte per pixel
+    ///
+    /// Any RGBA re-encoding of it is larger, like for a well-compressed JPEG photo.
+    fn gray_noise_png(size: u32) -> Vec<u8> {
+        fn crc32(data: &[u8]) -> u32 {
+            let mut crc = !0u32;
+            for byte in data {
+                crc ^= *byte as u32;
+                for _ in 0..8 {
+                    crc = if crc & 1 == 1 {
+                        (crc >> 1) ^ 0xEDB8_8320
+                    } else {
+                        crc >> 1
+                    };
+                }
+            }
+            !crc
+        }
+        fn chunk(png: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+            png.extend((data.len() as u32).to_be_bytes());
+            let start = png.len();
+            png.extend(kind);
+            png.extend(data);
+            let crc = crc32(&png[start..]);
+            png.extend(crc.to_be_bytes());
+        }
+
+        let mut seed = 0x2545_F491u32;
+        let mut raw = Vec::new();
+        for _ in 0..size {
+            raw.extend([0]); // no filter
+            for _ in 0..size {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                raw.push(seed as u8);
+            }
+        }
+
+        let mut zlib = vec![0x78, 0x01];
+        let blocks = raw.chunks(u16::MAX as usize).collect::<Vec<_>>();
+        for (i, block) in blocks.iter().enumerate() {
+            zlib.push((i + 1 == blocks.len()) as u8);
+            let len = block.len() as u16;
+            zlib.extend(len.to_le_bytes());
+            zlib.extend((!len).to_le_bytes());
+            zlib.extend(*block);
+        }
+        let (a, b) = raw.iter().fold((1u32, 0u32), |(a, b), byte| {
+            let a = (a + *byte as u32) % 65521;
+            (a, (b + a) % 65521)
+        });
+        zlib.extend(((b << 16) | a).to_be_bytes());
+
+        let mut header = Vec::new();
+        header.extend(size.to_be_bytes());
+        header.extend(size.to_be_bytes());
+        header.extend([8, 0, 0, 0, 0]); // 8-bit grayscale
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        chunk(&mut png, b"IHDR", &header);
+        chunk(&mut png, b"IDAT", &zlib);
+        chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    #[test]
+    fn downscales_large_images() {
+        let png = tiny_skia::Pixmap::new(400, 200)
+            .unwrap()
+            .encode_png()
+            .unwrap();
+        let resized = downscale(&image(png), target(100, 100)).unwrap();
+        assert_eq!(resized.mime_type, "image/png");
+        assert_eq!(resized.size, Some((200.0, 100.0)));
+        assert_eq!(imagesize::blob_size(&resized.bytes).unwrap().width, 200);
+    }
+
+    #[test]
+    fn keeps_small_images() {
+        let png = tiny_skia::Pixmap::new(40, 20)
+            .unwrap()
+            .encode_png()
+            .unwrap();
+        assert!(downscale(&image(png), target(100, 100)).is_none());
+    }
+
+    #[test]
+    fn keeps_originals_that_would_grow() {
+        let png = gray_noise_png(200);
+        assert_eq!(imagesize::blob_size(&png).unwrap().width, 200);
+        assert!(downscale(&image(png.clone()), target(190, 190)).is_none());
+        // Shrunk enough, even the PNG is smaller
+        assert!(downscale(&image(png), target(40, 40)).is_some());
+    }
+}
@@ -1,64 +1,208 @@
+use async_trait::async_trait;
+use hickory_resolver::config::{NameServerConfigGroup, ResolverConfig};
+use hickory_resolver::name_server::TokioConnectionProvider;
 use hickory_resolver::{Resolver, TokioResolver};
 use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use std::collections::HashMap;
 use std::io;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 
-/// DNS resolver that only allows global (public) IP addresses
+use super::error::ImageFetchError;
+use super::policy::HostPolicy;
+
+/// Hostname to IP address lookup used by `GlobalResolver`
+///
+/// Lookups only resolve names; the resolver applies the SSRF and host policy checks to
+/// whatever addresses they return.
+#[async_trait]
+pub trait NameLookup: Send + Sync {
+    async fn lookup(&self, host: &str) -> io::Result<Vec<IpAddr>>;
+}
+
+/// Lookup through DNS, using the system configuration unless nameservers are given
+pub struct SystemLookup {
+    inner: TokioResolver,
+}
+
+impl SystemLookup {
+    /// Query `nameservers` (`ip` or `ip:port`), or the system resolvers if empty
+    pub fn new(nameservers: &[String]) -> Result<Self, Box<dyn std::error::Error>> {
+        if nameservers.is_empty() {
+            let resolver: TokioResolver =
+                Resolver::builder_tokio().map_err(io::Error::other)?.build();
+            return Ok(Self { inner: resolver });
+        }
+
+        let mut group = NameServerConfigGroup::new();
+        for server in nameservers {
+            let addr = server
+                .parse::<SocketAddr>()
+                .or_else(|_| server.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+                .map_err(|_| format!("invalid DNS server: {}", server))?;
+            group.merge(NameServerConfigGroup::from_ips_clear(
+                &[addr.ip()],
+                addr.port(),
+                false,
+            ));
+        }
+        tracing::info!("Resolving image hosts via {}", nameservers.join(", "));
+
+        let config = ResolverConfig::from_parts(None, Vec::new(), group);
+        let resolver =
+            Resolver::builder_with_config(config, TokioConnectionProvider::default()).build();
+        Ok(Self { inner: resolver })
+    }
+}
+
+#[async_trait]
+impl NameLookup for SystemLookup {
+    async fn lookup(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        let lookup = self.inner.lookup_ip(host).await.map_err(io::Error::other)?;
+        Ok(lookup.iter().collect())
+    }
+}
+
+/// Lookup that answers hosts from a static table, delegating every other host
+pub struct StaticLookup {
+    hosts: HashMap<String, Vec<IpAddr>>,
+    inner: Arc<dyn NameLookup>,
+}
+
+impl StaticLookup {
+    /// Parse `host=ip` overrides; a host may be listed several times for several addresses
+    pub fn new(overrides: &[String], inner: Arc<dyn NameLookup>) -> Result<Self, String> {
+        let mut hosts: HashMap<String, Vec<IpAddr>> = HashMap::new();
+        for entry in overrides {
+            let (host, ip) = entry
+                .split_once('=')
+                .and_then(|(host, ip)| Some((host.trim(), ip.trim().parse::<IpAddr>().ok()?)))
+                .filter(|(host, _)| !host.is_empty())
+                .ok_or_else(|| format!("invalid host override (expected host=ip): {}", entry))?;
+            hosts
+                .entry(host.trim_end_matches('.').to_ascii_lowercase())
+                .or_default()
+                .push(ip);
+        }
+
+        Ok(Self { hosts, inner })
+    }
+}
+
+#[async_trait]
+impl NameLookup for StaticLookup {
+    async fn lookup(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        let key = host.trim_end_matches('.').to_ascii_lowercase();
+        match self.hosts.get(&key) {
+            Some(ips) => {
+                tracing::debug!("Using static addresses for {}", host);
+                Ok(ips.clone())
+            }
+            None => self.inner.lookup(host).await,
+        }
+    }
+}
+
+/// DNS resolver that only allows global (public) IP addresses permitted by the host policy
+///
+/// Non-global addresses fail the whole lookup, unless `filter_private` is set, in which case
+/// they are dropped and the remaining addresses used.
 #[derive(Clone)]
 pub struct GlobalResolver {
-    inner: Arc<TokioResolver>,
+    lookup: Arc<dyn NameLookup>,
+    policy: Arc<HostPolicy>,
+    filter_private: bool,
+    trusted: Option<String>,
 }
 
 impl GlobalResolver {
-    pub fn new() -> io::Result<Self> {
-        let resolver: TokioResolver = Resolver::builder_tokio()
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
-            .build();
+    pub fn new(policy: Arc<HostPolicy>, lookup: Arc<dyn NameLookup>, filter_private: bool) -> Self {
+        Self {
+            lookup,
+            policy,
+            filter_private,
+            trusted: None,
+        }
+    }
 
-        Ok(Self {
-            inner: Arc::new(resolver),
-        })
+    /// Resolve `host` without any checks (the configured egress proxy)
+    pub fn with_trusted_host(mut self, host: &str) -> Self {
+        self.trusted = Some(host.trim_end_matches('.').to_ascii_lowercase());
+        self
     }
 }
 
 impl Resolve for GlobalResolver {
     fn resolve(&self, name: Name) -> Resolving {
-        let resolver = self.inner.clone();
+        let lookup = self.lookup.clone();
+        let policy = self.policy.clone();
+        let filter_private = self.filter_private;
+        let trusted = self.trusted.clone();
         let name_str = name.as_str().to_string();
 
         Box::pin(async move {
             // Resolve DNS
-            let lookup = resolver.lookup_ip(name.as_str()).await.map_err(|e| {
-                Box::new(io::Error::new(io::ErrorKind::Other, e))
-                    as Box<dyn std::error::Error + Send + Sync>
-            })?;
+            let ips = lookup
+                .lookup(name.as_str())
+                .await
+                .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
+
+            if trusted.as_deref() == Some(name_str.trim_end_matches('.')) {
+                tracing::debug!("Resolved trusted host {} without checks", name_str);
+                let addrs: Vec<SocketAddr> =
+                    ips.into_iter().map(|ip| SocketAddr::new(ip, 0)).collect();
+                return Ok(Box::new(addrs.into_iter()) as Addrs);
+            }
 
             let mut addrs = Vec::new();
+            let mut filtered = None;
 
             // Check all resolved IPs
-            for ip in lookup.iter() {
-                // Block private/local IPs
-                if !ip_rfc::global(&ip) {
+            for ip in ips {
+                // Block private/local IPs outside the SSRF exception networks
+                if !policy.is_reachable(&ip) {
+                    if filter_private {
+                        tracing::debug!("Ignoring private IP {} for {}", ip, name_str);
+                        filtered = Some(ip);
+                        continue;
+                    }
                     tracing::warn!(
                         "Blocked DNS resolution for {} to private IP: {}",
                         name_str,
                         ip
                     );
-                    return Err(Box::new(io::Error::new(
-                        io::ErrorKind::PermissionDenied,
-                        format!(
-                            "SSRF protection: hostname {} resolves to private IP {}",
-                            name_str, ip
-                        ),
-                    ))
+                    return Err(Box::new(ImageFetchError::PrivateIpBlocked(format!(
+                        "hostname {} resolves to private IP {}",
+                        name_str, ip
+                    )))
                         as Box<dyn std::error::Error + Send + Sync>);
                 }
 
-                // All IPs are public, add to socket addresses
+                // Block addresses outside the configured allow/deny networks
+                if let Err(e) = policy.check_ip(&name_str, ip) {
+                    tracing::warn!("Blocked DNS resolution for {}: {}", name_str, e);
+                    return Err(Box::new(e) as Box<dyn std::error::Error + Send + Sync>);
+                }
+
+                // IP is public (or explicitly allowed), add to socket addresses
                 addrs.push(SocketAddr::new(ip, 0));
             }
 
+            if addrs.is_empty()
+                && let Some(ip) = filtered
+            {
+                tracing::warn!(
+                    "Blocked DNS resolution for {}: only private IPs (e.g. {})",
+                    name_str,
+                    ip
+                );
+                return Err(Box::new(ImageFetchError::PrivateIpBlocked(format!(
+                    "hostname {} only resolves to private IPs",
+                    name_str
+                )))
+                    as Box<dyn std::error::Error + Send + Sync>);
+            }
+
             if addrs.is_empty() {
                 return Err(Box::new(io::Error::new(
                     io::ErrorKind::NotFound,
@@ -67,7 +211,7 @@ impl Resolve for GlobalResolver {
                     as Box<dyn std::error::Error + Send + Sync>);
             }
 
-            tracing::debug!("Resolved {} to {} public IP(s)", name_str, addrs.len());
+            tracing::debug!("Resolved {} to {} permitted IP(s)", name_str, addrs.len());
 
             Ok(Box::new(addrs.into_iter()) as Addrs)
         })
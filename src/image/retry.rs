@@ -0,0 +1,56 @@
+use std::time::{Duration, Instant};
+
+use super::error::ImageFetchError;
+
+/// Bounded retries with jittered exponential backoff for transient fetch failures
+///
+/// All attempts share one time budget: each attempt gets the remaining budget as its timeout,
+/// and no retry is started if its backoff delay would not fit.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub backoff: Duration,
+    pub budget: Duration,
+}
+
+impl RetryPolicy {
+    /// Run `attempt` (given its timeout) until it succeeds, fails permanently or runs out of retries
+    pub async fn run<T, F, Fut>(&self, url: &str, mut attempt: F) -> Result<T, ImageFetchError>
+    where
+        F: FnMut(Duration) -> Fut,
+        Fut: Future<Output = Result<T, ImageFetchError>>,
+    {
+        let deadline = Instant::now() + self.budget;
+        let mut retries = 0;
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let error = match attempt(remaining).await {
+                Err(e) if retries < self.max_retries && e.is_transient() => e,
+                result => return result,
+            };
+
+            let delay = self.delay(retries);
+            if delay >= deadline.saturating_duration_since(Instant::now()) {
+                return Err(error);
+            }
+
+            retries += 1;
+            tracing::warn!(
+                "Fetching {} failed ({}), retry {}/{} in {:?}",
+                url,
+                error,
+                retries,
+                self.max_retries,
+                delay
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Full jitter: a random delay up to the exponential backoff for this retry
+    fn delay(&self, retry: u32) -> Duration {
+        let ceiling = self.backoff.saturating_mul(1 << retry.min(16));
+        ceiling.mul_f64(rand::random::<f64>())
+    }
+}
@@ -0,0 +1,227 @@
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, Writer};
+use std::io::Cursor;
+
+use super::error::ImageFetchError;
+
+/// Elements removed together with all of their children
+const DROPPED_ELEMENTS: &[&[u8]] = &[
+    b"script",
+    b"foreignObject",
+    b"iframe",
+    b"embed",
+    b"object",
+    b"audio",
+    b"video",
+    // Animations can set attributes (e.g. href) after sanitization
+    b"animate",
+    b"animateMotion",
+    b"animateTransform",
+    b"set",
+];
+
+/// Stage 4: Rewrite an SVG image so it can neither run code nor load other resources
+///
+/// Removes scripts, `<foreignObject>` and other embedding elements, animations, event handler
+/// attributes, every `href` that is not a same-document `#fragment` (which also drops nested
+/// data URIs), external `url(...)` references in attributes and stylesheets, and the doctype
+/// (custom entities).
+pub fn sanitize_svg(bytes: &[u8]) -> Result<Vec<u8>, ImageFetchError> {
+    let mut reader = Reader::from_reader(bytes);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    let mut buf = Vec::new();
+
+    // Depth inside a dropped element (0 means not dropping)
+    let mut drop_depth = 0usize;
+    // Buffered <style> element, written only if its contents are safe
+    let mut style: Option<(BytesStart<'static>, Vec<Event<'static>>, String)> = None;
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buf)
+            .map_err(|_| ImageFetchError::InvalidContentType)?;
+
+        if drop_depth > 0 {
+            match event {
+                Event::Start(_) => drop_depth += 1,
+                Event::End(_) => drop_depth -= 1,
+                Event::Eof => break,
+                _ => {}
+            }
+            buf.clear();
+            continue;
+        }
+
+        if let Some((_, events, css)) = style.as_mut() {
+            match event {
+                Event::End(_) => {
+                    let (start, events, css) = style.take().unwrap();
+                    if is_safe_css(&css) {
+                        write(&mut writer, Event::Start(start.clone()))?;
+                        for e in events {
+                            write(&mut writer, e)?;
+                        }
+                        write(&mut writer, Event::End(start.to_end().into_owned()))?;
+                    } else {
+                        tracing::warn!("Removed <style> with external references from SVG");
+                    }
+                }
+                Event::Text(e) => {
+                    css.push_str(&e.decode().unwrap_or_default());
+                    events.push(Event::Text(e.into_owned()));
+                }
+                Event::CData(e) => {
+                    css.push_str(&e.decode().unwrap_or_default());
+                    events.push(Event::CData(e.into_owned()));
+                }
+                Event::Eof => break,
+                // Stylesheets have no child elements; anything else is dropped
+                _ => {}
+            }
+            buf.clear();
+            continue;
+        }
+
+        match event {
+            Event::Start(e) => {
+                if DROPPED_ELEMENTS.contains(&e.local_name().as_ref()) {
+                    drop_depth = 1;
+                } else if e.local_name().as_ref() == b"style" {
+                    style = Some((sanitize_element(&e, &reader), Vec::new(), String::new()));
+                } else {
+                    write(&mut writer, Event::Start(sanitize_element(&e, &reader)))?;
+                }
+            }
+            Event::Empty(e) => {
+                if !DROPPED_ELEMENTS.contains(&e.local_name().as_ref()) {
+                    write(&mut writer, Event::Empty(sanitize_element(&e, &reader)))?;
+                }
+            }
+            // Doctypes can declare entities; processing instructions and comments are never needed
+            Event::DocType(_) | Event::PI(_) | Event::Comment(_) => {}
+            Event::Eof => break,
+            e => write(&mut writer, e)?,
+        }
+
+        buf.clear();
+    }
+
+    Ok(writer.into_inner().into_inner())
+}
+
+/// Copy an element, keeping only attributes that are safe to render
+fn sanitize_element(e: &BytesStart, reader: &Reader<&[u8]>) -> BytesStart<'static> {
+    let mut clean = BytesStart::new(String::from_utf8_lossy(e.name().as_ref()).into_owned());
+
+    for attr in e.attributes().filter_map(|a| a.ok()) {
+        let key = attr.key.as_ref();
+        let local = attr.key.local_name();
+
+        // Check the unescaped value so entity-encoded payloads are caught
+        let Ok(value) = attr.decode_and_unescape_value(reader.decoder()) else {
+            continue;
+        };
+        let lowered = value.trim().to_ascii_lowercase();
+
+        let unsafe_attr = key.len() > 2 && key[..2].eq_ignore_ascii_case(b"on")
+            || local.as_ref() == b"href" && !lowered.starts_with('#')
+            || lowered.contains("javascript:")
+            || !is_safe_css(&lowered);
+
+        if unsafe_attr {
+            tracing::debug!(
+                "Removed attribute {} from SVG <{}>",
+                String::from_utf8_lossy(key),
+                String::from_utf8_lossy(e.name().as_ref())
+            );
+            continue;
+        }
+
+        clean.push_attribute((key, attr.value.as_ref()));
+    }
+
+    clean
+}
+
+/// Whether CSS (or an attribute value) only references same-document fragments
+fn is_safe_css(css: &str) -> bool {
+    let css = css.to_ascii_lowercase();
+    if css.contains("@import") || css.contains("expression(") {
+        return false;
+    }
+
+    css.match_indices("url(").all(|(idx, _)| {
+        css[idx + 4..]
+            .trim_start()
+            .trim_start_matches(['\'', '"'])
+            .starts_with('#')
+    })
+}
+
+fn write(writer: &mut Writer<Cursor<Vec<u8>>>, event: Event) -> Result<(), ImageFetchError> {
+    writer
+        .write_event(event)
+        .map_err(|_| ImageFetchError::InvalidContentType)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sanitize(svg: &str) -> String {
+        String::from_utf8(sanitize_svg(svg.as_bytes()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn drops_scripts_and_foreign_objects() {
+        let svg = sanitize(
+            r#"<svg><script>alert(1)</script><foreignObject><div><script/></div></foreignObject><rect/></svg>"#,
+        );
+        assert_eq!(svg, "<svg><rect/></svg>");
+    }
+
+    #[test]
+    fn drops_event_handlers() {
+        let svg = sanitize(r#"<svg onload="alert(1)"><rect ONCLICK="alert(1)" fill="red"/></svg>"#);
+        assert_eq!(svg, r#"<svg><rect fill="red"/></svg>"#);
+    }
+
+    #[test]
+    fn keeps_only_fragment_hrefs() {
+        let svg = sanitize(
+            r##"<svg><use href="#a"/><use href="https://evil.test/x.svg#a"/><use xlink:href="data:image/svg+xml,x"/><image xlink:href="#b"/></svg>"##,
+        );
+        assert_eq!(
+            svg,
+            r##"<svg><use href="#a"/><use/><use/><image xlink:href="#b"/></svg>"##
+        );
+    }
+
+    #[test]
+    fn drops_external_css_references() {
+        let svg = sanitize(
+            r##"<svg><rect fill="url(#grad)"/><rect fill="url( 'https://evil.test/x' )"/><rect style="fill: url(https://evil.test/x)"/></svg>"##,
+        );
+        assert_eq!(
+            svg,
+            r##"<svg><rect fill="url(#grad)"/><rect/><rect/></svg>"##
+        );
+
+        let svg =
+            sanitize(r#"<svg><style>@import "https://evil.test/x.css";</style><rect/></svg>"#);
+        assert_eq!(svg, "<svg><rect/></svg>");
+        let svg = sanitize(r#"<svg><style>rect { fill: url(#grad) }</style></svg>"#);
+        assert_eq!(
+            svg,
+            r#"<svg><style>rect { fill: url(#grad) }</style></svg>"#
+        );
+    }
+
+    #[test]
+    fn drops_entity_encoded_javascript_urls() {
+        let svg = sanitize(
+            r#"<svg><a href="&#106;avascript:alert(1)"><rect fill="&#x6A;avascript:alert(1)"/></a></svg>"#,
+        );
+        assert_eq!(svg, "<svg><a><rect/></a></svg>");
+    }
+}
@@ -0,0 +1,267 @@
+use async_trait::async_trait;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, Proxy};
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+use super::circuit::CircuitBreaker;
+use super::error::ImageFetchError;
+use super::fetch::{self, FetchedImage, Revalidation};
+use super::freshness::CacheHeaders;
+use super::parse;
+use super::policy::{HostPattern, HostPolicy};
+use super::resolver::{GlobalResolver, NameLookup};
+use super::retry::RetryPolicy;
+use crate::config::ImageSettings;
+
+/// Backend that retrieves raw image bytes for a URL
+///
+/// Sources only fetch; validation, caching and downscaling are done by `ImageFetcher` for
+/// every source alike.
+#[async_trait]
+pub trait ImageSource: Send + Sync {
+    /// Fetch the image behind `url`
+    async fn fetch(&self, url: &str) -> Result<FetchedImage, ImageFetchError>;
+
+    /// Re-fetch a cached image, sending its validators if the source supports them
+    ///
+    /// Defaults to a full fetch.
+    async fn revalidate(
+        &self,
+        url: &str,
+        cached: &CacheHeaders,
+    ) -> Result<Revalidation, ImageFetchError> {
+        let _ = cached;
+        self.fetch(url).await.map(Revalidation::Modified)
+    }
+}
+
+/// Request headers sent only to hosts matching a pattern
+struct HostHeader {
+    host: HostPattern,
+    name: HeaderName,
+    value: HeaderValue,
+}
+
+impl HostHeader {
+    /// Parse `host=Name:Value`, where host is exact or `*.example.com`
+    fn parse(entry: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid host header (expected host=Name:Value): {}", entry);
+
+        let (host, header) = entry.split_once('=').ok_or_else(invalid)?;
+        let (name, value) = header.split_once(':').ok_or_else(invalid)?;
+
+        let host = match host.parse::<HostPattern>()? {
+            HostPattern::Cidr(_) => return Err(invalid()),
+            pattern => pattern,
+        };
+        let name = HeaderName::try_from(name.trim()).map_err(|_| invalid())?;
+        let mut value = HeaderValue::try_from(value.trim()).map_err(|_| invalid())?;
+        value.set_sensitive(true);
+
+        Ok(Self { host, name, value })
+    }
+}
+
+/// Fetches images over HTTP(S), with SSRF protection from `GlobalResolver`
+///
+/// Transient failures are retried within the total timeout, and hosts that keep failing are
+/// short-circuited by a per-host circuit breaker.
+///
+/// With an egress proxy configured, the proxy resolves image hosts itself, so the addresses
+/// of hostnames are never checked here: URLs are still checked against hostname patterns and
+/// for private IP literals, but network patterns are refused as they could not be enforced.
+pub struct HttpSource {
+    client: Client,
+    max_size: usize,
+    allow_http: bool,
+    policy: Arc<HostPolicy>,
+    headers: Arc<Vec<HostHeader>>,
+    retry: RetryPolicy,
+    circuit: CircuitBreaker,
+}
+
+impl HttpSource {
+    pub fn new(
+        settings: &ImageSettings,
+        policy: Arc<HostPolicy>,
+        lookup: Arc<dyn NameLookup>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let headers = Arc::new(
+            settings
+                .host_headers
+                .iter()
+                .map(|entry| HostHeader::parse(entry))
+                .collect::<Result<Vec<_>, _>>()?,
+        );
+
+        // Create global-only DNS resolver (SSRF protection)
+        let mut resolver = GlobalResolver::new(policy.clone(), lookup, settings.filter_private_ips);
+
+        // Create HTTP client with custom resolver and timeouts
+        let mut builder = Client::builder()
+            .timeout(Duration::from_secs(settings.total_timeout_secs))
+            .connect_timeout(Duration::from_secs(settings.connect_timeout_secs))
+            .user_agent(settings.user_agent.as_str())
+            .redirect(Self::redirect_policy(
+                settings.max_redirects,
+                settings.allow_http,
+                policy.clone(),
+                headers.clone(),
+            ));
+
+        if let Some(proxy) = &settings.proxy {
+            if policy.has_networks() {
+                return Err(
+                    "CIDR host patterns cannot be enforced with an egress proxy, \
+                            which resolves image hosts itself"
+                        .into(),
+                );
+            }
+
+            let proxy_url = Url::parse(proxy).map_err(|e| format!("invalid proxy URL: {}", e))?;
+            let proxy_host = proxy_url
+                .host_str()
+                .ok_or_else(|| format!("proxy URL has no host: {}", proxy))?;
+
+            // The proxy is usually on the private network; it is the only host exempt from SSRF checks
+            resolver = resolver.with_trusted_host(proxy_host);
+            builder = builder.proxy(Proxy::all(proxy_url.as_str())?);
+            tracing::info!(
+                "Fetching images through {} proxy at {}",
+                proxy_url.scheme(),
+                proxy_host
+            );
+        }
+
+        let client = builder.dns_resolver(Arc::new(resolver)).build()?;
+
+        Ok(Self {
+            client,
+            max_size: settings.max_size_bytes,
+            allow_http: settings.allow_http,
+            policy,
+            headers,
+            retry: RetryPolicy {
+                max_retries: settings.max_retries,
+                backoff: Duration::from_millis(settings.retry_backoff_ms),
+                budget: Duration::from_secs(settings.total_timeout_secs),
+            },
+            circuit: CircuitBreaker::new(
+                settings.circuit_failure_threshold,
+                Duration::from_secs(settings.circuit_open_secs),
+            ),
+        })
+    }
+
+    /// Follow redirects up to the limit, re-validating every hop like the initial URL
+    ///
+    /// Redirects that leave a host with configured headers are refused, as those headers
+    /// (typically credentials) would be forwarded to the new host.
+    fn redirect_policy(
+        max_redirects: usize,
+        allow_http: bool,
+        policy: Arc<HostPolicy>,
+        headers: Arc<Vec<HostHeader>>,
+    ) -> reqwest::redirect::Policy {
+        reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() > max_redirects {
+                return attempt.error(ImageFetchError::Request("too many redirects".to_string()));
+            }
+
+            let origin = attempt
+                .previous()
+                .first()
+                .and_then(|url| url.host_str())
+                .map(str::to_ascii_lowercase);
+            let leaves_origin = origin.as_deref() != attempt.url().host_str();
+            if let Some(origin) = origin
+                && leaves_origin
+                && headers.iter().any(|h| h.host.matches_host(&origin))
+            {
+                return attempt.error(ImageFetchError::Request(format!(
+                    "refusing redirect from {} to another host with configured headers",
+                    origin
+                )));
+            }
+
+            match parse::parse_url(attempt.url().as_str(), allow_http, &policy) {
+                Ok(_) => attempt.follow(),
+                Err(e) => attempt.error(e),
+            }
+        })
+    }
+
+    /// Configured headers for the host of `parsed`
+    fn headers_for(&self, parsed: &parse::ParsedUrl) -> HeaderMap {
+        let host = parsed
+            .url
+            .host_str()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        self.headers
+            .iter()
+            .filter(|h| h.host.matches_host(&host))
+            .map(|h| (h.name.clone(), h.value.clone()))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl ImageSource for HttpSource {
+    async fn fetch(&self, url: &str) -> Result<FetchedImage, ImageFetchError> {
+        let parsed = parse::parse_url(url, self.allow_http, &self.policy)?;
+        let host = parsed
+            .url
+            .host_str()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        self.circuit.check(&host)?;
+
+        let headers = self.headers_for(&parsed);
+        let result = self
+            .retry
+            .run(url, |timeout| {
+                let (parsed, headers) = (parsed.clone(), headers.clone());
+                fetch::fetch_http(parsed, &self.client, headers, timeout, self.max_size)
+            })
+            .await;
+
+        self.circuit.record(&host, &result);
+        result
+    }
+
+    async fn revalidate(
+        &self,
+        url: &str,
+        cached: &CacheHeaders,
+    ) -> Result<Revalidation, ImageFetchError> {
+        let parsed = parse::parse_url(url, self.allow_http, &self.policy)?;
+        let host = parsed
+            .url
+            .host_str()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        self.circuit.check(&host)?;
+
+        let headers = self.headers_for(&parsed);
+        let result = self
+            .retry
+            .run(url, |timeout| {
+                let (parsed, headers) = (parsed.clone(), headers.clone());
+                fetch::revalidate_http(
+                    parsed,
+                    &self.client,
+                    headers,
+                    timeout,
+                    self.max_size,
+                    cached,
+                )
+            })
+            .await;
+
+        self.circuit.record(&host, &result);
+        result
+    }
+}
@@ -1,15 +1,50 @@
+use quick_xml::Reader;
+use quick_xml::events::{BytesStart, Event};
+use svgtypes::{Length, LengthUnit, ViewBox};
+
 use super::error::ImageFetchError;
 use super::fetch::FetchedImage;
+use super::sanitize::sanitize_svg;
 
+#[derive(Clone)]
 pub struct ValidatedImage {
     pub bytes: Vec<u8>,
     pub mime_type: String,
+    /// Width and height in the image's own units, probed during validation; None if they
+    /// cannot be determined
+    pub size: Option<(f32, f32)>,
+}
+
+/// Limits guarding against images that are small on the wire but huge once decoded
+#[derive(Debug, Clone, Copy)]
+pub struct ImageLimits {
+    /// Maximum width x height for raster images
+    pub max_pixels: u64,
+    /// Maximum number of elements in an SVG document
+    pub svg_max_nodes: usize,
+    /// Maximum element nesting depth in an SVG document
+    pub svg_max_depth: usize,
+}
+
+/// Detect the MIME type of image bytes using magic numbers
+///
+/// `infer` has no SVG matcher (it reports XML at best), so SVG is sniffed from the root element.
+pub fn detect_mime(bytes: &[u8]) -> Option<&'static str> {
+    match infer::get(bytes) {
+        Some(kind) if kind.mime_type() != "text/xml" => Some(kind.mime_type()),
+        _ => is_svg(bytes).then_some("image/svg+xml"),
+    }
 }
 
-/// Stage 3: Validate content type using magic numbers
-pub fn validate_content_type(fetched: FetchedImage) -> Result<ValidatedImage, ImageFetchError> {
+/// Stage 3: Validate content type using magic numbers, then probe decoded dimensions
+///
+/// SVG images are also sanitized here, so only cleaned bytes are cached and embedded.
+pub fn validate_content_type(
+    fetched: FetchedImage,
+    limits: &ImageLimits,
+) -> Result<ValidatedImage, ImageFetchError> {
     // Validate content-type using magic numbers (not headers!)
-    let kind = infer::get(&fetched.bytes).ok_or_else(|| {
+    let mime_type = detect_mime(&fetched.bytes).ok_or_else(|| {
         tracing::warn!(
             "Could not determine file type for image from {}",
             fetched.url
@@ -18,7 +53,6 @@ pub fn validate_content_type(fetched: FetchedImage) -> Result<ValidatedImage, Im
     })?;
 
     // Allow only safe image types
-    let mime_type = kind.mime_type();
     if !matches!(
         mime_type,
         "image/png" | "image/jpeg" | "image/gif" | "image/webp" | "image/svg+xml"
@@ -27,15 +61,185 @@ pub fn validate_content_type(fetched: FetchedImage) -> Result<ValidatedImage, Im
         return Err(ImageFetchError::InvalidContentType);
     }
 
+    // Decompression bomb protection: check declared size before anything decodes it
+    let probed = if mime_type == "image/svg+xml" {
+        probe_svg(&fetched.bytes, limits)
+    } else {
+        probe_raster(&fetched.bytes, limits).map(Some)
+    };
+    let size = probed.inspect_err(|e| {
+        tracing::warn!("Rejected image from {}: {}", fetched.url, e);
+    })?;
+
+    let bytes = if mime_type == "image/svg+xml" {
+        sanitize_svg(&fetched.bytes).inspect_err(|_| {
+            tracing::warn!("Failed to sanitize SVG image from {}", fetched.url);
+        })?
+    } else {
+        fetched.bytes
+    };
+
     tracing::info!(
         "Successfully validated {} image ({} bytes) from {}",
         mime_type,
-        fetched.bytes.len(),
+        bytes.len(),
         fetched.url
     );
 
     Ok(ValidatedImage {
-        bytes: fetched.bytes,
+        bytes,
         mime_type: mime_type.to_string(),
+        size: size.filter(|(width, height)| *width > 0.0 && *height > 0.0),
     })
 }
+
+/// Read raster dimensions from the image header and enforce the pixel budget
+fn probe_raster(bytes: &[u8], limits: &ImageLimits) -> Result<(f32, f32), ImageFetchError> {
+    let size = imagesize::blob_size(bytes).map_err(|_| ImageFetchError::InvalidContentType)?;
+
+    let pixels = size.width as u64 * size.height as u64;
+    if pixels > limits.max_pixels {
+        return Err(ImageFetchError::TooComplex(format!(
+            "{}x{} pixels exceeds the limit of {}",
+            size.width, size.height, limits.max_pixels
+        )));
+    }
+
+    Ok((size.width as f32, size.height as f32))
+}
+
+/// Count elements and nesting depth of an SVG document without building a tree, and read
+/// its size from the root element
+fn probe_svg(bytes: &[u8], limits: &ImageLimits) -> Result<Option<(f32, f32)>, ImageFetchError> {
+    let mut reader = Reader::from_reader(bytes);
+    let mut buf = Vec::new();
+    let mut nodes = 0;
+    let mut depth: usize = 0;
+    let mut size = None;
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buf)
+            .map_err(|_| ImageFetchError::InvalidContentType)?;
+
+        if nodes == 0
+            && let Event::Start(e) | Event::Empty(e) = &event
+        {
+            size = svg_size(e);
+        }
+
+        match event {
+            Event::Start(_) => {
+                nodes += 1;
+                depth += 1;
+            }
+            Event::Empty(_) => nodes += 1,
+            Event::End(_) => depth = depth.saturating_sub(1),
+            Event::Eof => break,
+            _ => {}
+        }
+
+        if nodes > limits.svg_max_nodes {
+            return Err(ImageFetchError::TooComplex(format!(
+                "SVG has more than {} elements",
+                limits.svg_max_nodes
+            )));
+        }
+        if depth > limits.svg_max_depth {
+            return Err(ImageFetchError::TooComplex(format!(
+                "SVG nesting exceeds depth {}",
+                limits.svg_max_depth
+            )));
+        }
+
+        buf.clear();
+    }
+
+    Ok(size)
+}
+
+/// Size of an SVG document from its root element, resolved like the renderer does
+///
+/// Missing or percentage dimensions are relative to the viewBox, or to 100 without one.
+fn svg_size(root: &BytesStart) -> Option<(f32, f32)> {
+    let attr = |name: &str| {
+        root.try_get_attribute(name)
+            .ok()
+            .flatten()
+            .map(|a| String::from_utf8_lossy(&a.value).into_owned())
+    };
+    let view_box = attr("viewBox").and_then(|v| v.parse::<ViewBox>().ok());
+
+    let length = |name: &str, view_box_length: Option<f64>| {
+        let length = match attr(name) {
+            Some(value) => value.parse::<Length>().ok()?,
+            None => Length::new(100.0, LengthUnit::Percent),
+        };
+        let n = length.number;
+        let pixels = match length.unit {
+            LengthUnit::None | LengthUnit::Px => n,
+            LengthUnit::Em => n * 12.0,
+            LengthUnit::Ex => n * 6.0,
+            LengthUnit::In => n * 96.0,
+            LengthUnit::Cm => n * 96.0 / 2.54,
+            LengthUnit::Mm => n * 96.0 / 25.4,
+            LengthUnit::Pt => n * 96.0 / 72.0,
+            LengthUnit::Pc => n * 96.0 / 6.0,
+            LengthUnit::Percent => n / 100.0 * view_box_length.unwrap_or(100.0),
+        };
+        Some(pixels as f32)
+    };
+
+    Some((
+        length("width", view_box.map(|v| v.w))?,
+        length("height", view_box.map(|v| v.h))?,
+    ))
+}
+
+/// Check whether the document's root element is `<svg>`
+fn is_svg(bytes: &[u8]) -> bool {
+    let mut reader = Reader::from_reader(bytes);
+    let mut buf = Vec::new();
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e) | Event::Empty(e)) => return e.local_name().as_ref() == b"svg",
+            Ok(Event::Eof) | Err(_) => return false,
+            // Skip XML declaration, comments, doctype and whitespace
+            Ok(_) => buf.clear(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size_of(svg: &str) -> Option<(f32, f32)> {
+        let limits = ImageLimits {
+            max_pixels: u64::MAX,
+            svg_max_nodes: 100,
+            svg_max_depth: 10,
+        };
+        probe_svg(svg.as_bytes(), &limits).unwrap()
+    }
+
+    #[test]
+    fn probes_svg_size_like_the_renderer() {
+        let tree = |svg: &str| {
+            let size = usvg::Tree::from_str(svg, &usvg::Options::default())
+                .unwrap()
+                .size();
+            Some((size.width(), size.height()))
+        };
+
+        for svg in [
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="120" height="40"/>"#,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 300 150"/>"#,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="50%" viewBox="0 0 300 150"/>"#,
+            r#"<?xml version="1.0"?><svg xmlns="http://www.w3.org/2000/svg" width="1in" height="3em"/>"#,
+        ] {
+            assert_eq!(size_of(svg), tree(svg), "{}", svg);
+        }
+    }
+}
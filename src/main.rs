@@ -11,13 +11,16 @@ use std::sync::Arc;
 #[derive(Clone)]
 pub struct ImageState {
     pub fetcher: Arc<image::ImageFetcher>,
-    pub fallback: config::ImageFallbackBehavior,
+    pub fallback: config::FallbackPolicy,
 }
 
 #[derive(Clone)]
 pub struct AppState {
-    pub fontdb: Arc<usvg::fontdb::Database>,
+    pub fonts: Arc<generator::Fonts>,
+    pub templates: Arc<generator::Templates>,
     pub max_input_length: usize,
+    pub max_data_uri_length: usize,
+    pub max_upload_size: usize,
     pub defaults: config::Defaults,
     pub image: ImageState,
 }
@@ -36,24 +39,23 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Load fonts
     let fontdb = fonts::load_fonts();
 
+    // Load templates, resolving their includes and base templates once
+    let templates =
+        generator::Templates::load(config.templates_dir.as_deref(), &config.default_template)?;
+
     // Initialize image fetcher with SSRF protection
-    let image_fetcher = Arc::new(image::ImageFetcher::new(
-        config.image.connect_timeout_secs,
-        config.image.total_timeout_secs,
-        config.image.max_size_bytes,
-        config.image.cache_size,
-        config.image.cache_ttl_secs,
-        config.image.max_redirects,
-        config.image.allow_http,
-    )?);
+    let image_fetcher = Arc::new(image::ImageFetcher::new(&config.image)?);
 
     let state = AppState {
-        fontdb: Arc::new(fontdb),
+        fonts: Arc::new(generator::Fonts::new(fontdb)),
+        templates: Arc::new(templates),
         max_input_length: config.max_input_length,
+        max_data_uri_length: config.max_data_uri_length,
+        max_upload_size: config.max_upload_size,
         defaults: config.defaults,
         image: ImageState {
             fetcher: image_fetcher,
-            fallback: config.image.fallback,
+            fallback: config::FallbackPolicy::from_settings(&config.image)?,
         },
     };
 
@@ -1,11 +1,20 @@
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
-use serde::Deserialize;
+use futures_util::future::join_all;
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
 use utoipa::{IntoParams, ToSchema};
 
 use crate::AppState;
 use crate::config::ImageFallbackBehavior;
-use crate::image::ValidatedImage;
+use crate::generator::{
+    self, FocalPoint, ImageFilters, ImageFit, ImageOverlay, ImageStyle, MaskShape, SlotImage,
+    Template,
+};
+use crate::image::{TargetSize, ValidatedImage, describe_source, is_data_uri};
+
+/// Image bytes uploaded as multipart file parts, by field name
+pub type Uploads = HashMap<String, Vec<u8>>;
 
 #[derive(Debug, Deserialize, IntoParams, ToSchema)]
 #[into_params(parameter_in = Query)]
@@ -19,92 +28,411 @@ pub struct OgParams {
     /// Subtitle text (above title)
     #[serde(default)]
     pub subtitle: Option<String>,
-    /// Optional logo image URL
+    /// Comma-separated tags shown as chips, e.g. `rust,svg,og`
+    #[serde(default)]
+    pub tags: Option<String>,
+    /// Optional logo image URL or `data:image/...` URI
     #[serde(default)]
     pub logo: Option<String>,
-    /// Optional custom image URL
+    /// Optional custom image URL or `data:image/...` URI
     #[serde(default)]
     pub image: Option<String>,
+    /// Optional full-bleed background image URL or `data:image/...` URI
+    #[serde(default)]
+    pub background: Option<String>,
+    /// Point of the background kept in view when cropped, as `x,y` fractions (e.g. `0.5,0.2`)
+    #[serde(default)]
+    pub background_focus: Option<String>,
+    /// Filters applied to the background, e.g. `grayscale(1) blur(4)`
+    #[serde(default)]
+    pub background_filter: Option<String>,
+    /// Layer between the background and the text: a color (e.g. `#000000aa`),
+    /// `linear-gradient([angle,] color, color, ...)` or `none`
+    #[serde(default)]
+    pub background_overlay: Option<String>,
+    /// How the logo is sized to its slot
+    #[serde(default)]
+    pub logo_fit: Option<ImageFit>,
+    /// Point of the logo kept in view when cropped, as `x,y` fractions (e.g. `0.5,0.2`)
+    #[serde(default)]
+    pub logo_focus: Option<String>,
+    /// Shape the logo is clipped to
+    #[serde(default)]
+    pub logo_mask: Option<MaskShape>,
+    /// Corner radius for the `rounded` logo mask
+    #[serde(default, deserialize_with = "number_from_str")]
+    pub logo_radius: Option<f32>,
+    /// Filters applied to the logo, e.g. `grayscale(1) opacity(0.8)`
+    #[serde(default)]
+    pub logo_filter: Option<String>,
+    /// How the custom image is sized to its slot
+    #[serde(default)]
+    pub image_fit: Option<ImageFit>,
+    /// Point of the custom image kept in view when cropped, as `x,y` fractions (e.g. `0.5,0.2`)
+    #[serde(default)]
+    pub image_focus: Option<String>,
+    /// Shape the custom image is clipped to
+    #[serde(default)]
+    pub image_mask: Option<MaskShape>,
+    /// Corner radius for the `rounded` custom image mask
+    #[serde(default, deserialize_with = "number_from_str")]
+    pub image_radius: Option<f32>,
+    /// Filters applied to the custom image: `grayscale(a)`, `blur(px)`, `brightness(a)`,
+    /// `opacity(a)`, `tint(color, a)` and `duotone(dark, light)`, e.g. `brightness(0.6) tint(#1a1a2e, 0.4)`
+    #[serde(default)]
+    pub image_filter: Option<String>,
+    /// Template to render, by file name without `.svg` (default: the server's default template)
+    #[serde(default)]
+    pub template: Option<String>,
+    /// Theme declared by the template with `<ogis:theme>`, e.g. `dark` or `light` for the
+    /// built-in `twilight` template. The template's parameters, such as its colors, can also
+    /// be set one by one
+    #[serde(default)]
+    pub theme: Option<String>,
+    /// Behavior when an image cannot be loaded, overriding the server's default fallback
+    /// (but not the fallbacks the server sets for specific errors)
+    #[serde(default)]
+    pub on_image_error: Option<ImageFallbackBehavior>,
+    /// Image URLs or `data:` URIs for the template's other image slots, by slot name
+    /// (`ogis_img_avatar` is filled from `img_avatar`), comma-separated items for its
+    /// other list slots, and values for the template's declared parameters
+    #[serde(flatten)]
+    #[param(ignore)]
+    pub extra: HashMap<String, String>,
 }
 
 impl OgParams {
-    /// Validate input parameters against maximum length
-    pub fn validate(&self, max_length: usize) -> Result<(), String> {
-        let fields = [
-            ("Title", &self.title),
-            ("Description", &self.description),
-            ("Subtitle", &self.subtitle),
-            ("Logo URL", &self.logo),
-            ("Image URL", &self.image),
+    /// Validate input parameters against maximum length and the template's declarations
+    ///
+    /// Image fields holding `data:` URIs are checked against their own, larger limit; text
+    /// fields always use `max_length`.
+    pub fn validate(
+        &self,
+        template: &Template,
+        max_length: usize,
+        max_data_uri_length: usize,
+    ) -> Result<(), String> {
+        let text_fields = [
+            ("Title".to_string(), self.title.as_ref()),
+            ("Description".to_string(), self.description.as_ref()),
+            ("Subtitle".to_string(), self.subtitle.as_ref()),
+            ("Logo filter".to_string(), self.logo_filter.as_ref()),
+            ("Image filter".to_string(), self.image_filter.as_ref()),
+            (
+                "Background filter".to_string(),
+                self.background_filter.as_ref(),
+            ),
+            (
+                "Background overlay".to_string(),
+                self.background_overlay.as_ref(),
+            ),
         ];
+        let list_fields = template
+            .list_slots()
+            .iter()
+            .map(|name| (format!("{} list", name), self.list_source(name)));
 
-        for (name, field) in fields {
-            if let Some(value) = field {
-                if value.len() > max_length {
-                    return Err(format!("{} exceeds maximum length of {}", name, max_length));
-                }
+        for (name, field) in text_fields.into_iter().chain(list_fields) {
+            if let Some(value) = field
+                && value.len() > max_length
+            {
+                return Err(format!("{} exceeds maximum length of {}", name, max_length));
+            }
+        }
+
+        let image_fields = [
+            ("Logo URL".to_string(), self.logo.as_ref()),
+            ("Image URL".to_string(), self.image.as_ref()),
+            ("Background URL".to_string(), self.background.as_ref()),
+        ];
+        let slot_fields = template
+            .image_slots()
+            .keys()
+            .map(|name| (format!("{} URL", name), self.extra.get(name)));
+
+        for (name, field) in image_fields.into_iter().chain(slot_fields) {
+            let Some(value) = field else {
+                continue;
+            };
+
+            let limit = if is_data_uri(value) {
+                max_data_uri_length
+            } else {
+                max_length
+            };
+            if value.len() > limit {
+                return Err(format!("{} exceeds maximum length of {}", name, limit));
+            }
+        }
+
+        self.template_values(template)?;
+
+        let focuses = [&self.logo_focus, &self.image_focus, &self.background_focus];
+        for focus in focuses.into_iter().flatten() {
+            focus.parse::<FocalPoint>()?;
+        }
+
+        let filters = [
+            &self.logo_filter,
+            &self.image_filter,
+            &self.background_filter,
+        ];
+        for filter in filters.into_iter().flatten() {
+            filter.parse::<ImageFilters>()?;
+        }
+
+        if let Some(overlay) = &self.background_overlay {
+            overlay.parse::<ImageOverlay>()?;
+        }
+
+        for radius in [self.logo_radius, self.image_radius].into_iter().flatten() {
+            if !(radius >= 0.0 && radius.is_finite()) {
+                return Err(format!("Invalid mask radius: {}", radius));
             }
         }
 
         Ok(())
     }
 
-    /// Fetch logo image if URL provided, respecting fallback behavior
-    pub async fn fetch_logo(&self, state: &AppState) -> Result<Option<ValidatedImage>, Response> {
-        self.fetch_image_from_url(&self.logo, "logo", state).await
+    /// Values of the template's declared parameters, validated, with the theme's or the
+    /// declared defaults for unset ones
+    pub fn template_values(&self, template: &Template) -> Result<HashMap<String, String>, String> {
+        template
+            .params()
+            .resolve(&self.extra, self.theme.as_deref())
+    }
+
+    /// Requested presentation of the logo
+    pub fn logo_style(&self) -> ImageStyle {
+        ImageStyle {
+            fit: self.logo_fit,
+            focus: self.logo_focus.as_deref().and_then(|f| f.parse().ok()),
+            mask: self.logo_mask,
+            radius: self.logo_radius,
+            filter: self.logo_filter.as_deref().and_then(|f| f.parse().ok()),
+            overlay: None,
+        }
+    }
+
+    /// Requested presentation of the custom image
+    pub fn image_style(&self) -> ImageStyle {
+        ImageStyle {
+            fit: self.image_fit,
+            focus: self.image_focus.as_deref().and_then(|f| f.parse().ok()),
+            mask: self.image_mask,
+            radius: self.image_radius,
+            filter: self.image_filter.as_deref().and_then(|f| f.parse().ok()),
+            overlay: None,
+        }
+    }
+
+    /// Requested presentation of the background
+    pub fn background_style(&self) -> ImageStyle {
+        ImageStyle {
+            focus: self
+                .background_focus
+                .as_deref()
+                .and_then(|f| f.parse().ok()),
+            filter: self
+                .background_filter
+                .as_deref()
+                .and_then(|f| f.parse().ok()),
+            overlay: self
+                .background_overlay
+                .as_deref()
+                .and_then(|o| o.parse().ok()),
+            ..ImageStyle::default()
+        }
+    }
+
+    /// Upload-less source requested for a template image slot
+    fn slot_source(&self, name: &str) -> Option<&String> {
+        match name {
+            "logo" => self.logo.as_ref(),
+            "image" => self.image.as_ref(),
+            "background" => self.background.as_ref(),
+            other => self.extra.get(other),
+        }
     }
 
-    /// Fetch custom image if URL provided, respecting fallback behavior
-    pub async fn fetch_image(&self, state: &AppState) -> Result<Option<ValidatedImage>, Response> {
-        self.fetch_image_from_url(&self.image, "image", state).await
+    /// Requested presentation of the image in a template slot
+    fn slot_style(&self, name: &str) -> ImageStyle {
+        match name {
+            "logo" => self.logo_style(),
+            "image" => self.image_style(),
+            "background" => self.background_style(),
+            _ => ImageStyle::default(),
+        }
+    }
+
+    /// Comma-separated items requested for a template list slot
+    fn list_source(&self, name: &str) -> Option<&String> {
+        match name {
+            "tags" => self.tags.as_ref(),
+            other => self.extra.get(other),
+        }
+    }
+
+    /// Items for every template list slot, split on commas with blank items left out
+    pub fn list_items(&self, template: &Template) -> HashMap<String, Vec<String>> {
+        template
+            .list_slots()
+            .iter()
+            .filter_map(|name| {
+                let items = self
+                    .list_source(name)?
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                Some((name.clone(), items))
+            })
+            .collect()
     }
 
-    /// Helper to fetch an image from a URL with error handling
-    async fn fetch_image_from_url(
+    /// Fetch images for every template slot with an upload or URL, respecting fallback behavior
+    ///
+    /// Slots are fetched concurrently. Slots without an image are left out of the result.
+    pub async fn fetch_slot_images(
+        &self,
+        template: &Template,
+        state: &AppState,
+        uploads: &mut Uploads,
+    ) -> Result<HashMap<String, SlotImage>, Response> {
+        let fetches = template.image_slots().iter().map(|(name, size)| {
+            let upload = uploads.remove(name);
+            async move {
+                let image = self.fetch_slot_image(name, *size, upload, state).await?;
+                let style = self.slot_style(name);
+                Ok(image.map(|image| (name.clone(), SlotImage { image, style })))
+            }
+        });
+
+        join_all(fetches)
+            .await
+            .into_iter()
+            .filter_map(Result::transpose)
+            .collect()
+    }
+
+    /// Helper to fetch a slot's image with error handling
+    ///
+    /// An uploaded file part with the slot's name takes precedence over the URL. Uploads that
+    /// fail validation are the client's fault and always rejected, whatever the fallback.
+    async fn fetch_slot_image(
         &self,
-        url_option: &Option<String>,
         name: &str,
+        size: Option<(f32, f32)>,
+        upload: Option<Vec<u8>>,
         state: &AppState,
     ) -> Result<Option<ValidatedImage>, Response> {
-        if let Some(url) = url_option {
-            match state.image.fetcher.fetch_image(url).await {
-                Ok(validated) => {
-                    tracing::info!("Successfully fetched {} from: {}", name, url);
-                    Ok(Some(validated))
-                }
-                Err(e) => match state.image.fallback {
-                    ImageFallbackBehavior::Skip => {
-                        tracing::warn!(
-                            "Failed to fetch {} from {}: {} - skipping {} element",
-                            name,
-                            url,
-                            e,
-                            name
-                        );
-                        Ok(None)
+        // Downscale to the size of the template slot this image will fill
+        let target = size.and_then(|(width, height)| {
+            TargetSize::from_slot(width, height, generator::OUTPUT_SCALE)
+        });
+
+        let (source, result) = match (upload, self.slot_source(name)) {
+            (Some(bytes), _) => {
+                return match state.image.fetcher.load_upload(bytes, name, target) {
+                    Ok(validated) => {
+                        tracing::info!("Successfully loaded uploaded {}", name);
+                        Ok(Some(validated))
                     }
-                    ImageFallbackBehavior::Error => {
-                        tracing::error!("Failed to fetch {} from {}: {}", name, url, e);
+                    Err(e) => {
+                        tracing::warn!("Invalid upload for {}: {}", name, e);
                         Err((
-                            StatusCode::INTERNAL_SERVER_ERROR,
-                            format!("Failed to fetch {}: {}", name, e),
+                            StatusCode::BAD_REQUEST,
+                            format!("Invalid input: uploaded {}: {}", name, e),
                         )
                             .into_response())
                     }
-                },
+                };
+            }
+            (None, Some(url)) => (
+                describe_source(url),
+                state.image.fetcher.fetch_image(url, target).await,
+            ),
+            (None, None) => return Ok(None),
+        };
+        match result {
+            Ok(validated) => {
+                tracing::info!("Successfully fetched {} from: {}", name, source);
+                Ok(Some(validated))
             }
-        } else {
-            Ok(None)
+            Err(e) => match state.image.fallback.behavior(e.kind(), self.on_image_error) {
+                ImageFallbackBehavior::Skip => {
+                    tracing::warn!(
+                        "Failed to fetch {} from {}: {} - skipping {} element",
+                        name,
+                        source,
+                        e,
+                        name
+                    );
+                    Ok(None)
+                }
+                ImageFallbackBehavior::Error => {
+                    tracing::error!("Failed to fetch {} from {}: {}", name, source, e);
+                    Err((
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("Failed to fetch {}: {}", name, e),
+                    )
+                        .into_response())
+                }
+                ImageFallbackBehavior::Placeholder => {
+                    tracing::warn!(
+                        "Failed to fetch {} from {}: {} - using placeholder",
+                        name,
+                        source,
+                        e
+                    );
+                    Ok(Self::fetch_placeholder(name, target, state).await)
+                }
+            },
         }
     }
 
-    /// Apply defaults for missing parameters
-    pub fn with_defaults(&self, state: &AppState) -> (String, String, String) {
+    /// Load the configured placeholder image, skipping the element if there is none
+    async fn fetch_placeholder(
+        name: &str,
+        target: Option<TargetSize>,
+        state: &AppState,
+    ) -> Option<ValidatedImage> {
+        let Some(placeholder) = &state.image.fallback.placeholder else {
+            tracing::warn!(
+                "No placeholder image configured - skipping {} element",
+                name
+            );
+            return None;
+        };
+
+        match state.image.fetcher.fetch_image(placeholder, target).await {
+            Ok(validated) => Some(validated),
+            Err(e) => {
+                tracing::error!(
+                    "Failed to fetch placeholder from {}: {} - skipping {} element",
+                    describe_source(placeholder),
+                    e,
+                    name
+                );
+                None
+            }
+        }
+    }
+
+    /// Text for the template's text slots, applying defaults for missing parameters
+    pub fn with_defaults(&self, template: &Template, state: &AppState) -> HashMap<String, String> {
         let no_params = self.title.is_none()
             && self.description.is_none()
             && self.subtitle.is_none()
-            && self.logo.is_none()
-            && self.image.is_none();
+            && template
+                .image_slots()
+                .keys()
+                .all(|name| self.slot_source(name).is_none())
+            && template
+                .list_slots()
+                .iter()
+                .all(|name| self.list_source(name).is_none());
 
         let get = |param: &Option<String>, default: &str| {
             if no_params {
@@ -114,10 +442,26 @@ impl OgParams {
             }
         };
 
-        (
-            get(&self.title, &state.defaults.title),
-            get(&self.description, &state.defaults.description),
-            get(&self.subtitle, &state.defaults.subtitle),
-        )
+        HashMap::from([
+            ("title".to_string(), get(&self.title, &state.defaults.title)),
+            (
+                "description".to_string(),
+                get(&self.description, &state.defaults.description),
+            ),
+            (
+                "subtitle".to_string(),
+                get(&self.subtitle, &state.defaults.subtitle),
+            ),
+        ])
     }
 }
+
+/// Deserialize an optional number from its string form
+///
+/// Flattening `extra` makes serde buffer every query value as a string first, which the
+/// plain number deserializers reject.
+fn number_from_str<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f32>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|value| value.trim().parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
@@ -4,6 +4,7 @@ use utoipa::OpenApi;
 #[openapi(
     paths(
         crate::routes::index::generate,
+        crate::routes::upload::generate,
         crate::routes::health::health_check
     ),
     components(schemas(crate::params::OgParams)),
@@ -1,9 +1,16 @@
-use crate::{AppState, generator, params::OgParams};
+use crate::{
+    AppState,
+    generator::{self, Template},
+    image::{TargetSize, ValidatedImage},
+    params::{OgParams, Uploads},
+};
 use axum::{
     extract::{Query, State},
     http::{StatusCode, header},
-    response::IntoResponse,
+    response::{IntoResponse, Response},
 };
+use futures_util::future::join_all;
+use std::collections::HashMap;
 
 #[utoipa::path(
     get,
@@ -20,31 +27,80 @@ pub async fn generate(
     State(state): State<AppState>,
     Query(params): Query<OgParams>,
 ) -> impl IntoResponse {
-    // Validate input lengths
-    if let Err(err) = params.validate(state.max_input_length) {
+    render(&state, params, Uploads::new()).await
+}
+
+/// Validate params, fetch images, generate the SVG and render it to PNG
+///
+/// Shared by the query string and multipart upload endpoints.
+pub async fn render(state: &AppState, params: OgParams, mut uploads: Uploads) -> Response {
+    // Look up the requested template
+    let template = match state.templates.get(params.template.as_deref()) {
+        Ok(template) => template,
+        Err(err) => {
+            tracing::warn!("Input validation failed: {}", err);
+            return (StatusCode::BAD_REQUEST, format!("Invalid input: {}", err)).into_response();
+        }
+    };
+
+    // Validate input lengths and template parameters
+    if let Err(err) = params.validate(template, state.max_input_length, state.max_data_uri_length) {
         tracing::warn!("Input validation failed: {}", err);
         return (StatusCode::BAD_REQUEST, format!("Invalid input: {}", err)).into_response();
     }
 
+    // Every uploaded file must fill one of the template's image slots
+    if let Some(name) = uploads
+        .keys()
+        .find(|name| !template.image_slots().contains_key(*name))
+    {
+        tracing::warn!("Input validation failed: upload for unknown slot {}", name);
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Invalid input: template has no image slot named {}", name),
+        )
+            .into_response();
+    }
+
     tracing::info!("Generating OG image with params: {:?}", params);
 
-    // Fetch logo image if URL provided
-    let logo = match params.fetch_logo(&state).await {
-        Ok(img) => img,
+    // Fetch images for the template's slots if uploaded or URL provided
+    let images = match params
+        .fetch_slot_images(template, state, &mut uploads)
+        .await
+    {
+        Ok(images) => images,
         Err(response) => return response,
     };
 
-    // Fetch custom image if URL provided
-    let image = match params.fetch_image(&state).await {
-        Ok(img) => img,
-        Err(response) => return response,
-    };
+    // Load images the template references from the assets directory
+    let assets = fetch_template_assets(template, state).await;
 
     // Apply defaults for missing params
-    let (title, description, subtitle) = params.with_defaults(&state);
+    let text = params.with_defaults(template, state);
+
+    // Items for the template's list slots, such as tags
+    let lists = params.list_items(template);
+
+    // Template parameter values
+    let values = match params.template_values(template) {
+        Ok(values) => values,
+        Err(err) => {
+            tracing::warn!("Input validation failed: {}", err);
+            return (StatusCode::BAD_REQUEST, format!("Invalid input: {}", err)).into_response();
+        }
+    };
 
     // Generate SVG
-    let svg_data = match generator::generate_svg(&title, &description, &subtitle, logo, image) {
+    let svg_data = match generator::generate_svg(
+        template,
+        text,
+        images,
+        lists,
+        values,
+        assets,
+        &state.fonts,
+    ) {
         Ok(data) => data,
         Err(err) => {
             tracing::error!("Failed to generate SVG: {}", err);
@@ -57,7 +113,7 @@ pub async fn generate(
     };
 
     // Render SVG to PNG
-    match generator::render_to_png(&svg_data, &state.fontdb) {
+    match generator::render_to_png(&svg_data, &state.fonts) {
         Ok(png_data) => (
             StatusCode::OK,
             [(header::CONTENT_TYPE, "image/png")],
@@ -74,3 +130,27 @@ pub async fn generate(
         }
     }
 }
+
+/// Load every `asset:` image the template references, concurrently
+///
+/// Assets that fail to load are left out and their elements dropped from the output.
+async fn fetch_template_assets(
+    template: &Template,
+    state: &AppState,
+) -> HashMap<String, ValidatedImage> {
+    let loads = template.assets().iter().map(|(href, size)| async move {
+        let target = size.and_then(|(width, height)| {
+            TargetSize::from_slot(width, height, generator::OUTPUT_SCALE)
+        });
+
+        match state.image.fetcher.fetch_image(href, target).await {
+            Ok(image) => Some((href.clone(), image)),
+            Err(e) => {
+                tracing::warn!("Failed to load template asset {}: {}", href, e);
+                None
+            }
+        }
+    });
+
+    join_all(loads).await.into_iter().flatten().collect()
+}
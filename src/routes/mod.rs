@@ -1,15 +1,23 @@
 pub mod docs;
 pub mod health;
 pub mod index;
+pub mod upload;
 
 use crate::AppState;
-use axum::{Router, routing::get};
+use axum::{Router, extract::DefaultBodyLimit, routing::get};
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
 pub fn create_router(state: AppState) -> Router {
+    let max_upload_size = state.max_upload_size;
+
     Router::new()
-        .route("/", get(index::generate))
+        .route(
+            "/",
+            get(index::generate)
+                .post(upload::generate)
+                .layer(DefaultBodyLimit::max(max_upload_size)),
+        )
         .route("/health", get(health::health_check))
         .with_state(state)
         .merge(SwaggerUi::new("/docs").url("/api-docs/openapi.json", docs::ApiDoc::openapi()))
@@ -0,0 +1,224 @@
+use crate::{
+    AppState,
+    params::{OgParams, Uploads},
+    routes::index::render,
+};
+use axum::{
+    extract::{Multipart, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{
+    Deserialize, Deserializer,
+    de::{
+        IntoDeserializer, Visitor,
+        value::{Error, MapDeserializer},
+    },
+    forward_to_deserialize_any,
+};
+
+#[utoipa::path(
+    post,
+    path = "/",
+    request_body(
+        content = OgParams,
+        content_type = "multipart/form-data",
+        description = "Text fields as form fields; `background`, `logo`, `image` and other template image slots may be uploaded as file parts instead of URLs"
+    ),
+    responses(
+        (status = 200, description = "Successfully generated PNG image (1200x630)", content_type = "image/png"),
+        (status = 400, description = "Invalid input - malformed multipart body, field exceeds maximum length, or upload is not a valid image or matches no image slot"),
+        (status = 413, description = "Request body too large"),
+        (status = 500, description = "Failed to generate image")
+    ),
+    tag = "image"
+)]
+pub async fn generate(State(state): State<AppState>, mut multipart: Multipart) -> Response {
+    // File parts become uploads, everything else is a regular parameter
+    let mut fields = Vec::new();
+    let mut uploads = Uploads::new();
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(err) => {
+                tracing::warn!("Invalid multipart request: {}", err);
+                return err.into_response();
+            }
+        };
+        let Some(name) = field.name().map(str::to_string) else {
+            return (
+                StatusCode::BAD_REQUEST,
+                "Invalid multipart body: part without a field name",
+            )
+                .into_response();
+        };
+
+        let data = if field.file_name().is_some() {
+            field.bytes().await.map(|data| {
+                tracing::debug!("Received upload {} ({} bytes)", name, data.len());
+                uploads.insert(name, data.to_vec());
+            })
+        } else {
+            field
+                .text()
+                .await
+                .map(|value| fields.push((name, FieldValue(value))))
+        };
+        if let Err(err) = data {
+            tracing::warn!("Invalid multipart request: {}", err);
+            return err.into_response();
+        }
+    }
+
+    // Deserialize the fields like a query string so both endpoints accept the same fields
+    let params = OgParams::deserialize(MapDeserializer::<_, Error>::new(fields.into_iter()));
+    let params = match params {
+        Ok(params) => params,
+        Err(err) => {
+            return (StatusCode::BAD_REQUEST, format!("Invalid input: {}", err)).into_response();
+        }
+    };
+
+    render(&state, params, uploads).await
+}
+
+/// Value of a text field, deserialized like a query string value: a field that is present is
+/// `Some`, and enums and numbers are parsed from its text
+struct FieldValue(String);
+
+impl IntoDeserializer<'_, Error> for FieldValue {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> Deserializer<'de> for FieldValue {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_string(self.0)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.0
+            .into_deserializer()
+            .deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        unit unit_struct newtype_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ImageState, config, generator, image::ImageFetcher};
+    use axum::{body::Body, extract::FromRequest, http::Request};
+    use clap::Parser;
+    use std::sync::Arc;
+
+    const BOUNDARY: &str = "ogis-test-boundary";
+
+    fn state() -> AppState {
+        let config = config::Config::try_parse_from(["ogis"]).unwrap();
+        AppState {
+            fonts: Arc::new(generator::Fonts::new(usvg::fontdb::Database::new())),
+            templates: Arc::new(generator::Templates::load(None, "twilight").unwrap()),
+            max_input_length: config.max_input_length,
+            max_data_uri_length: config.max_data_uri_length,
+            max_upload_size: config.max_upload_size,
+            defaults: config.defaults,
+            image: ImageState {
+                fetcher: Arc::new(ImageFetcher::new(&config.image).unwrap()),
+                fallback: config::FallbackPolicy::from_settings(&config.image).unwrap(),
+            },
+        }
+    }
+
+    /// Post `parts` as `(name, file name, content)` and return the status and body
+    async fn post(parts: &[(&str, Option<&str>, &[u8])]) -> (StatusCode, String) {
+        let mut body = Vec::new();
+        for (name, file_name, content) in parts {
+            body.extend(format!("--{}\r\n", BOUNDARY).bytes());
+            let file_name = file_name
+                .map(|file| format!("; filename=\"{}\"", file))
+                .unwrap_or_default();
+            body.extend(
+                format!(
+                    "Content-Disposition: form-data; name=\"{}\"{}\r\n\r\n",
+                    name, file_name
+                )
+                .bytes(),
+            );
+            body.extend(*content);
+            body.extend(b"\r\n");
+        }
+        body.extend(format!("--{}--\r\n", BOUNDARY).bytes());
+
+        let request = Request::post("/")
+            .header(
+                "content-type",
+                format!("multipart/form-data; boundary={}", BOUNDARY),
+            )
+            .body(Body::from(body))
+            .unwrap();
+        let multipart = Multipart::from_request(request, &()).await.unwrap();
+
+        let response = generate(State(state()), multipart).await;
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    fn png() -> Vec<u8> {
+        tiny_skia::Pixmap::new(4, 4).unwrap().encode_png().unwrap()
+    }
+
+    #[tokio::test]
+    async fn renders_uploaded_images() {
+        let png = png();
+        let (status, _) = post(&[
+            ("title", None, b"Uploaded"),
+            ("logo", Some("logo.png"), &png),
+        ])
+        .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn rejects_uploads_for_unknown_slots() {
+        let png = png();
+        let (status, body) = post(&[("img_zzz", Some("zzz.png"), &png)]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("img_zzz"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_uploaded_images() {
+        let (status, body) = post(&[("logo", Some("logo.png"), b"not an image")]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("logo"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_fields() {
+        let (status, _) = post(&[("logo_fit", None, b"sideways")]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}